@@ -6,10 +6,14 @@
 //! This module defines the nodes that make up the AST for dice expressions as
 //! well as a `Visitor` trait that can be used to traverse the AST.
 
-use std::error::Error;
-
-/// An abstract node in the syntax tree.
-pub trait Node: std::fmt::Debug {
+use std::{
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+/// An abstract node in the syntax tree. Nodes are plain data, so a tree can be
+/// shared between threads.
+pub trait Node: std::fmt::Debug + Send + Sync {
     fn accept(&self, visitor: &mut dyn Visitor) -> VisitorResult;
 }
 
@@ -26,6 +30,9 @@ pub trait Visitor {
     /// Visit a select node.
     fn select(&mut self, node: &Select) -> VisitorResult;
 
+    /// Visit a critical damage node.
+    fn crit(&mut self, node: &Crit) -> VisitorResult;
+
     /// Visit a negate node.
     fn neg(&mut self, node: &Neg) -> VisitorResult;
 
@@ -40,6 +47,18 @@ pub trait Visitor {
 
     /// Visit a divide node.
     fn div(&mut self, node: &Div) -> VisitorResult;
+
+    /// Visit a modulo node.
+    fn modulo(&mut self, node: &Mod) -> VisitorResult;
+
+    /// Visit an exponent node.
+    fn pow(&mut self, node: &Pow) -> VisitorResult;
+
+    /// Visit a function call node.
+    fn func(&mut self, node: &Func) -> VisitorResult;
+
+    /// Visit a comparison node.
+    fn compare(&mut self, node: &Compare) -> VisitorResult;
 }
 
 /// Selections that can be made over dice rolls.
@@ -64,6 +83,69 @@ pub enum Selection {
     Disadvantage,
 }
 
+/// Comparisons that can be made between a die result and a target number.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Comparison {
+    /// The result is strictly less than the target.
+    Less,
+
+    /// The result is less than or equal to the target.
+    LessEqual,
+
+    /// The result is equal to the target.
+    Equal,
+
+    /// The result is greater than or equal to the target.
+    GreaterEqual,
+
+    /// The result is strictly greater than the target.
+    Greater,
+}
+
+/// Functions that can be called with a list of arguments.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Function {
+    /// Round a single argument down to an integer.
+    Floor,
+
+    /// Round a single argument up to an integer.
+    Ceil,
+
+    /// Round a single argument to the nearest integer, with halves rounded
+    /// away from zero.
+    Round,
+
+    /// The absolute value of a single argument.
+    Abs,
+
+    /// The least of one or more arguments.
+    Min,
+
+    /// The greatest of one or more arguments.
+    Max,
+}
+
+/// Rules for increasing damage dealt by a critical hit.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CritRule {
+    /// Roll twice as many of each die.
+    Double,
+
+    /// Add the maximum result of each kept die to a normal roll.
+    Maximize,
+
+    /// Roll the whole expression the given number of times and sum the totals.
+    Multiply(i32),
+}
+
+/// A range of die results, such as `>=19`, that count as a critical success or
+/// failure.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Threshold {
+    pub comparison: Comparison,
+    pub value: i32,
+}
+
 /// Node that represents a literal integer value.
 #[derive(Debug)]
 pub struct Lit {
@@ -76,6 +158,14 @@ pub struct Roll {
     pub count: Box<dyn Node>,
     pub sides: Box<dyn Node>,
     pub select: Option<Box<dyn Node>>,
+
+    /// Results that count as a critical success. If not given, a natural 20 is
+    /// critical on a d20 and no other dice can crit.
+    pub crit: Option<Threshold>,
+
+    /// Results that count as a critical failure. If not given, a natural 1 is
+    /// a fumble on a d20 and no other dice can fumble.
+    pub fumble: Option<Threshold>,
 }
 
 /// A node that specifies some selection over previously rolled dice.
@@ -86,6 +176,19 @@ pub struct Select {
     pub next: Option<Box<dyn Node>>,
 }
 
+/// A node that applies a critical damage rule to every roll in an expression.
+#[derive(Debug)]
+pub struct Crit {
+    pub rule: CritRule,
+
+    /// The expression as written.
+    pub expr: Box<dyn Node>,
+
+    /// For a doubled critical, the expression rewritten to roll twice as many
+    /// dice.
+    doubled: Option<Box<dyn Node>>,
+}
+
 /// A node that represents the unary negation operation.
 #[derive(Debug)]
 pub struct Neg {
@@ -120,6 +223,126 @@ pub struct Div {
     pub right: Box<dyn Node>,
 }
 
+/// A node that represents the modulo operation. The result has the same sign
+/// as the divisor.
+#[derive(Debug)]
+pub struct Mod {
+    pub left: Box<dyn Node>,
+    pub right: Box<dyn Node>,
+}
+
+/// A node that represents raising the left operand to the power of the right.
+#[derive(Debug)]
+pub struct Pow {
+    pub left: Box<dyn Node>,
+    pub right: Box<dyn Node>,
+}
+
+/// A node that represents calling a function with a list of arguments.
+#[derive(Debug)]
+pub struct Func {
+    pub function: Function,
+    pub args: Vec<Box<dyn Node>>,
+}
+
+/// A node that represents comparing two expressions. The result is 1 if the
+/// comparison holds and 0 otherwise.
+#[derive(Debug)]
+pub struct Compare {
+    pub comparison: Comparison,
+    pub left: Box<dyn Node>,
+    pub right: Box<dyn Node>,
+}
+
+impl Comparison {
+    /// Test whether `lhs` compares to `rhs` according to this comparison.
+    pub fn test<T: PartialOrd>(self, lhs: T, rhs: T) -> bool {
+        match self {
+            Comparison::Less => lhs < rhs,
+            Comparison::LessEqual => lhs <= rhs,
+            Comparison::Equal => lhs == rhs,
+            Comparison::GreaterEqual => lhs >= rhs,
+            Comparison::Greater => lhs > rhs,
+        }
+    }
+}
+
+impl Crit {
+    /// Create a node that applies `rule` to `expr`. A doubled critical is
+    /// rewritten here, once, rather than by every evaluator.
+    pub fn new(rule: CritRule, expr: Box<dyn Node>) -> Result<Self, Box<dyn Error>> {
+        let doubled = match rule {
+            CritRule::Double => Some(crate::crit::double(expr.as_ref())?),
+            CritRule::Maximize | CritRule::Multiply(_) => None,
+        };
+
+        Ok(Self {
+            rule,
+            expr,
+            doubled,
+        })
+    }
+
+    /// The expression to evaluate, which for a doubled critical has already
+    /// been rewritten to roll twice as many dice.
+    pub fn operand(&self) -> &dyn Node {
+        self.doubled.as_deref().unwrap_or(self.expr.as_ref())
+    }
+}
+
+impl Threshold {
+    /// Test whether a die result falls within this threshold.
+    pub fn contains(&self, result: i32) -> bool {
+        self.comparison.test(result, self.value)
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Comparison::Less => write!(f, "<"),
+            Comparison::LessEqual => write!(f, "<="),
+            Comparison::Equal => write!(f, "="),
+            Comparison::GreaterEqual => write!(f, ">="),
+            Comparison::Greater => write!(f, ">"),
+        }
+    }
+}
+
+impl Display for Threshold {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}{}", self.comparison, self.value)
+    }
+}
+
+impl Selection {
+    /// The abbreviation by which the selection is written.
+    pub fn name(self) -> &'static str {
+        match self {
+            Selection::KeepHighest => "kh",
+            Selection::KeepLowest => "kl",
+            Selection::DropHighest => "dh",
+            Selection::DropLowest => "dl",
+            Selection::Advantage => "adv",
+            Selection::Disadvantage => "dis",
+        }
+    }
+}
+
+impl Function {
+    /// The name by which the function is called.
+    pub fn name(self) -> &'static str {
+        match self {
+            Function::Floor => "floor",
+            Function::Ceil => "ceil",
+            Function::Round => "round",
+            Function::Abs => "abs",
+            Function::Min => "min",
+            Function::Max => "max",
+        }
+    }
+}
+
 impl Node for Lit {
     fn accept(&self, visitor: &mut dyn Visitor) -> VisitorResult {
         visitor.lit(self)
@@ -138,6 +361,12 @@ impl Node for Select {
     }
 }
 
+impl Node for Crit {
+    fn accept(&self, visitor: &mut dyn Visitor) -> VisitorResult {
+        visitor.crit(self)
+    }
+}
+
 impl Node for Neg {
     fn accept(&self, visitor: &mut dyn Visitor) -> VisitorResult {
         visitor.neg(self)
@@ -167,3 +396,27 @@ impl Node for Div {
         visitor.div(self)
     }
 }
+
+impl Node for Mod {
+    fn accept(&self, visitor: &mut dyn Visitor) -> VisitorResult {
+        visitor.modulo(self)
+    }
+}
+
+impl Node for Pow {
+    fn accept(&self, visitor: &mut dyn Visitor) -> VisitorResult {
+        visitor.pow(self)
+    }
+}
+
+impl Node for Func {
+    fn accept(&self, visitor: &mut dyn Visitor) -> VisitorResult {
+        visitor.func(self)
+    }
+}
+
+impl Node for Compare {
+    fn accept(&self, visitor: &mut dyn Visitor) -> VisitorResult {
+        visitor.compare(self)
+    }
+}
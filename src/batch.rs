@@ -0,0 +1,223 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module rolls many expressions read from a file or standard input, one
+//! expression per line.
+//!
+//! Blank lines and lines beginning with `#` are skipped. A line that cannot be
+//! parsed or evaluated is reported with its line number, and the remaining
+//! lines are still rolled.
+
+use std::{
+    error::Error,
+    io::{BufRead, Write},
+};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::ast::Node;
+use crate::eval::{Arithmetic, DieRoll, Evaluation, Evaluator, Outcome};
+use crate::export::{json_string, Format};
+use crate::limits::Limits;
+use crate::parser::parse;
+use crate::pp::{self, PP};
+use crate::style::{bold, dim, print_error};
+use crate::template::{Template, Values};
+
+/// Rolls each expression of a batch and writes its result.
+pub struct Batch<'o, W: Write> {
+    /// The writer to which results are written.
+    writer: &'o mut W,
+
+    /// The format of the results, or `None` for text. JSON results are
+    /// written as JSON Lines, with one object per expression.
+    format: Option<Format>,
+
+    /// If given, each text result is written by filling in this template
+    /// instead.
+    pub template: Option<Template>,
+
+    /// The seed of the random number generator shared by every expression.
+    seed: u64,
+
+    /// The evaluator used for every expression of the batch.
+    evaluator: Evaluator<StdRng>,
+}
+
+impl<'o, W: Write> Batch<'o, W> {
+    pub fn new(
+        writer: &'o mut W,
+        format: Option<Format>,
+        arithmetic: Arithmetic,
+        seed: u64,
+    ) -> Self {
+        let mut evaluator = Evaluator::new(Evaluation::Rand(StdRng::seed_from_u64(seed)));
+        evaluator.arithmetic = arithmetic;
+
+        Self {
+            writer,
+            format,
+            template: None,
+            seed,
+            evaluator,
+        }
+    }
+
+    /// Roll every expression read from `reader` and return the number of
+    /// lines that failed.
+    pub fn run<R: BufRead>(&mut self, reader: R) -> Result<usize, Box<dyn Error>> {
+        let mut failures = 0;
+
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let input = line.trim();
+            if input.is_empty() || input.starts_with('#') {
+                continue;
+            }
+
+            if !self.roll(number + 1, input)? {
+                failures += 1;
+            }
+        }
+
+        Ok(failures)
+    }
+
+    /// Parse and roll a single expression.
+    fn eval(&mut self, input: &str) -> Result<(Box<dyn Node>, Outcome), Box<dyn Error>> {
+        let root = parse(&input.to_lowercase(), &Limits::default())?;
+        let outcome = self.evaluator.eval(root.as_ref())?;
+        Ok((root, outcome))
+    }
+
+    /// Roll a single expression and write its result or error. Returns
+    /// whether the expression was rolled successfully.
+    fn roll(&mut self, number: usize, input: &str) -> Result<bool, Box<dyn Error>> {
+        match (self.eval(input), self.format) {
+            (Ok((_, outcome)), Some(Format::Json)) => {
+                write_json(self.writer, number, input, &self.evaluator.rolls, &outcome)?;
+                Ok(true)
+            }
+
+            (Ok((root, outcome)), _) if self.template.is_some() => {
+                let expr = pp::to_string(root.as_ref())?;
+                let values = Values {
+                    expr: &expr,
+                    outcome: &outcome,
+                    rolls: &self.evaluator.rolls,
+                    seed: self.seed,
+                };
+
+                if let Some(template) = &self.template {
+                    writeln!(self.writer, "{}", template.render(&values))?;
+                }
+
+                Ok(true)
+            }
+
+            (Ok((root, outcome)), _) => {
+                write!(self.writer, "{} ", dim(format!("{number}:")))?;
+                root.accept(&mut PP::new(self.writer))?;
+                write!(self.writer, " {} {}", dim("="), bold(outcome.total))?;
+
+                for roll in &self.evaluator.rolls {
+                    write!(self.writer, " {roll}")?;
+                }
+
+                writeln!(self.writer)?;
+                Ok(true)
+            }
+
+            (Err(err), Some(Format::Json)) => {
+                writeln!(
+                    self.writer,
+                    "{{\"line\": {number}, \"expr\": {}, \"error\": {}}}",
+                    json_string(input),
+                    json_string(&err.to_string())
+                )?;
+                Ok(false)
+            }
+
+            (Err(err), _) => {
+                print_error(format!("line {number}: {err}"));
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Write the outcome of rolling `input` as a single line of JSON.
+fn write_json<W: Write>(
+    writer: &mut W,
+    number: usize,
+    input: &str,
+    rolls: &[DieRoll],
+    outcome: &Outcome,
+) -> std::io::Result<()> {
+    let dice: Vec<String> = rolls
+        .iter()
+        .map(|roll| {
+            format!(
+                "{{\"sides\": {}, \"result\": {}, \"kept\": {}}}",
+                roll.sides, roll.result, roll.keep
+            )
+        })
+        .collect();
+
+    writeln!(
+        writer,
+        "{{\"line\": {number}, \"expr\": {}, \"total\": {}, \"crits\": {}, \"fumbles\": {}, \"dice\": [{}]}}",
+        json_string(input),
+        outcome.total,
+        outcome.crits,
+        outcome.fumbles,
+        dice.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Roll each line of `input` with every die rolling its maximum, and
+    /// return what was written and the number of lines that failed.
+    fn run(input: &str, format: Option<Format>) -> (String, usize) {
+        let mut output = Vec::new();
+        let mut batch = Batch::new(&mut output, format, Arithmetic::Integer, 1);
+        batch.evaluator.evaluation = Evaluation::Max;
+        let failures = batch.run(input.as_bytes()).unwrap();
+        (String::from_utf8(output).unwrap(), failures)
+    }
+
+    #[test]
+    fn results_are_numbered_by_line() {
+        let (output, failures) = run("# comment\n1 + 2\n\n  3d4  \n", None);
+        assert_eq!(failures, 0);
+        assert_eq!(output, "2: 1 + 2 = 3\n4: 3d4 = 12 [d4:4] [d4:4] [d4:4]\n");
+    }
+
+    #[test]
+    fn bad_lines_are_reported_and_skipped() {
+        let (output, failures) = run("1d6\n1 +\n2d6kh1\nfoo\n3\n", None);
+        assert_eq!(failures, 2);
+        assert_eq!(
+            output,
+            "1: 1d6 = 6 [d6:6]\n3: 2d6kh1 = 6 [d6:6] ~~[d6:6]~~\n5: 3 = 3\n"
+        );
+    }
+
+    #[test]
+    fn errors_are_written_with_their_line_as_json() {
+        let (output, failures) = run("1d6\n1 +\n1/0\n2\n", Some(Format::Json));
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(failures, 2);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with(r#"{"line": 1, "expr": "1d6", "total": 6,"#));
+        assert_eq!(
+            lines[1],
+            r#"{"line": 2, "expr": "1 +", "error": "Unexpected end of input"}"#
+        );
+        assert!(lines[2].starts_with(r#"{"line": 3, "expr": "1/0", "error": "#));
+        assert!(lines[3].starts_with(r#"{"line": 4, "expr": "2", "total": 2,"#));
+    }
+}
@@ -0,0 +1,425 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module rewrites dice expressions into a canonical form and decides
+//! whether two expressions are equivalent.
+//!
+//! The same roll can be written many ways: `4d6d1`, `4d6dl1`, and `4d6kh3` all
+//! keep the highest three of four dice. Each run of keeps and drops between
+//! rerolls only narrows which of the sorted dice are kept, so it is rewritten
+//! as the fewest keeps that select the same dice. Chains inside a critical are
+//! left as written, since the critical may change how many dice they select
+//! from.
+
+use std::{error::Error, ops::Range};
+
+use crate::ast::{
+    Add, Compare, Crit, Div, Func, Lit, Mod, Mul, Neg, Node, Pow, Roll, Select, Selection, Sub,
+    Visitor, VisitorResult,
+};
+use crate::dist::DistEvaluator;
+use crate::eval::{Arithmetic, Error as EvalError};
+use crate::limits::Limits;
+use crate::pp;
+
+/// A rewritten sub-expression along with its value, if it is a literal.
+struct Rewritten {
+    node: Box<dyn Node>,
+    value: Option<i32>,
+}
+
+/// The rewritten operands of a binary operation.
+type Operands = (Box<dyn Node>, Box<dyn Node>);
+
+/// One selection of a chain, with its count rewritten.
+struct Step {
+    selection: Selection,
+    count: Option<Rewritten>,
+}
+
+/// An implementation of the `Visitor` trait that rebuilds an expression with
+/// every chain of selections in canonical form.
+pub struct Canonicalizer {
+    /// A stack of rewritten sub-expressions. Once the traversal is complete,
+    /// the stack should contain the rewritten expression.
+    results: Vec<Rewritten>,
+
+    /// The selections of the chain currently being visited.
+    steps: Vec<Step>,
+
+    /// How many criticals enclose the sub-expression currently being visited.
+    crits: usize,
+}
+
+/// Rewrite `node` into canonical form.
+pub fn canonicalize(node: &dyn Node) -> Result<Box<dyn Node>, Box<dyn Error>> {
+    let mut canonicalizer = Canonicalizer::new();
+    node.accept(&mut canonicalizer)?;
+    Ok(canonicalizer.pop()?)
+}
+
+/// Decide whether `left` and `right` have identical distributions. Expressions
+/// with the same canonical form are equivalent without computing either
+/// distribution.
+pub fn equivalent(
+    left: &dyn Node,
+    right: &dyn Node,
+    arithmetic: Arithmetic,
+    limits: &Limits,
+) -> Result<bool, Box<dyn Error>> {
+    // The pretty-printer writes every part of an expression that affects its
+    // value, so canonical forms that print the same are the same expression.
+    let canonical_left = pp::to_string(canonicalize(left)?.as_ref())?;
+    let canonical_right = pp::to_string(canonicalize(right)?.as_ref())?;
+    if canonical_left == canonical_right {
+        return Ok(true);
+    }
+
+    let mut evaluator = DistEvaluator::new();
+    evaluator.arithmetic = arithmetic;
+    evaluator.limits = *limits;
+
+    let left = evaluator.eval(left)?;
+    let right = evaluator.eval(right)?;
+    Ok(left.outcomes == right.outcomes)
+}
+
+impl Canonicalizer {
+    pub fn new() -> Self {
+        Self {
+            results: Vec::new(),
+            steps: Vec::new(),
+            crits: 0,
+        }
+    }
+
+    /// Pop a rewritten sub-expression from the stack.
+    fn pop(&mut self) -> Result<Box<dyn Node>, EvalError> {
+        self.pop_rewritten().map(|rewritten| rewritten.node)
+    }
+
+    /// Pop a rewritten sub-expression and its value from the stack.
+    fn pop_rewritten(&mut self) -> Result<Rewritten, EvalError> {
+        self.results.pop().ok_or(EvalError::StackUnderflow)
+    }
+
+    /// Push a rewritten sub-expression that is not a literal.
+    fn push(&mut self, node: impl Node + 'static) {
+        self.results.push(Rewritten {
+            node: Box::new(node),
+            value: None,
+        });
+    }
+
+    /// Rewrite both operands of a binary operation.
+    fn operands(&mut self, left: &dyn Node, right: &dyn Node) -> Result<Operands, Box<dyn Error>> {
+        left.accept(self)?;
+        let left = self.pop()?;
+        right.accept(self)?;
+        let right = self.pop()?;
+        Ok((left, right))
+    }
+}
+
+impl Default for Canonicalizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Rewrite a chain of selections over `count` dice into canonical form, or
+/// return `None` if any count is not a literal or selects more dice than
+/// remain, in which case the chain is left as written.
+fn normalize(count: Option<i32>, steps: &[Step]) -> Option<Vec<(Selection, Option<usize>)>> {
+    let mut size = usize::try_from(count?).ok()?;
+
+    // The dice kept by the current run of keeps and drops, as positions in
+    // ascending order of result.
+    let mut kept = 0..size;
+    let mut normal = Vec::new();
+
+    for step in steps {
+        match step.selection {
+            Selection::Advantage | Selection::Disadvantage => {
+                window(size, &kept, &mut normal);
+                normal.push((step.selection, None));
+
+                // A reroll only rolls the dice that are still kept.
+                size = kept.len();
+                kept = 0..size;
+            }
+
+            selection => {
+                let n = match &step.count {
+                    Some(count) => usize::try_from(count.value?).ok()?,
+                    None => 1,
+                };
+
+                if n > kept.len() {
+                    return None;
+                }
+
+                kept = match selection {
+                    Selection::KeepHighest => kept.end - n..kept.end,
+                    Selection::KeepLowest => kept.start..kept.start + n,
+                    Selection::DropHighest => kept.start..kept.end - n,
+                    _ => kept.start + n..kept.end,
+                };
+            }
+        }
+    }
+
+    window(size, &kept, &mut normal);
+    Some(normal)
+}
+
+/// Append the fewest selections that keep the dice at positions `kept` of
+/// `size` sorted dice.
+fn window(size: usize, kept: &Range<usize>, normal: &mut Vec<(Selection, Option<usize>)>) {
+    let count = kept.len();
+    if count == size {
+        return;
+    }
+
+    if count == 0 || kept.end == size {
+        normal.push((Selection::KeepHighest, Some(count)));
+    } else if kept.start == 0 {
+        normal.push((Selection::KeepLowest, Some(count)));
+    } else {
+        normal.push((Selection::KeepHighest, Some(size - kept.start)));
+        normal.push((Selection::KeepLowest, Some(count)));
+    }
+}
+
+impl Visitor for Canonicalizer {
+    fn lit(&mut self, node: &Lit) -> VisitorResult {
+        self.results.push(Rewritten {
+            node: Box::new(Lit { value: node.value }),
+            value: Some(node.value),
+        });
+        Ok(())
+    }
+
+    fn roll(&mut self, node: &Roll) -> VisitorResult {
+        node.count.accept(self)?;
+        let count = self.pop_rewritten()?;
+        node.sides.accept(self)?;
+        let sides = self.pop()?;
+
+        // Collect the chain of selections, keeping any enclosing chain aside.
+        let outer = std::mem::take(&mut self.steps);
+        let result = match &node.select {
+            Some(select) => select.accept(self),
+            None => Ok(()),
+        };
+        let steps = std::mem::replace(&mut self.steps, outer);
+        result?;
+
+        let normal = if self.crits == 0 {
+            normalize(count.value, &steps)
+        } else {
+            None
+        };
+
+        let mut select = None;
+        match normal {
+            Some(normal) => {
+                for (selection, count) in normal.into_iter().rev() {
+                    select = Some(Box::new(Select {
+                        selection,
+                        count: count.map(|value| {
+                            Box::new(Lit {
+                                value: value as i32,
+                            }) as Box<dyn Node>
+                        }),
+                        next: select,
+                    }) as Box<dyn Node>);
+                }
+            }
+
+            None => {
+                for step in steps.into_iter().rev() {
+                    select = Some(Box::new(Select {
+                        selection: step.selection,
+                        count: step.count.map(|count| count.node),
+                        next: select,
+                    }) as Box<dyn Node>);
+                }
+            }
+        }
+
+        self.push(Roll {
+            count: count.node,
+            sides,
+            select,
+            crit: node.crit,
+            fumble: node.fumble,
+        });
+        Ok(())
+    }
+
+    fn select(&mut self, node: &Select) -> VisitorResult {
+        let count = match &node.count {
+            Some(count) => {
+                count.accept(self)?;
+                Some(self.pop_rewritten()?)
+            }
+            None => None,
+        };
+
+        self.steps.push(Step {
+            selection: node.selection,
+            count,
+        });
+
+        if let Some(next) = &node.next {
+            next.accept(self)?;
+        }
+
+        Ok(())
+    }
+
+    fn crit(&mut self, node: &Crit) -> VisitorResult {
+        self.crits += 1;
+        let result = node.expr.accept(self);
+        self.crits -= 1;
+        result?;
+
+        let expr = self.pop()?;
+        self.push(Crit::new(node.rule, expr)?);
+        Ok(())
+    }
+
+    fn neg(&mut self, node: &Neg) -> VisitorResult {
+        node.right.accept(self)?;
+        let right = self.pop()?;
+        self.push(Neg { right });
+        Ok(())
+    }
+
+    fn add(&mut self, node: &Add) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Add { left, right });
+        Ok(())
+    }
+
+    fn sub(&mut self, node: &Sub) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Sub { left, right });
+        Ok(())
+    }
+
+    fn mul(&mut self, node: &Mul) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Mul { left, right });
+        Ok(())
+    }
+
+    fn div(&mut self, node: &Div) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Div { left, right });
+        Ok(())
+    }
+
+    fn modulo(&mut self, node: &Mod) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Mod { left, right });
+        Ok(())
+    }
+
+    fn pow(&mut self, node: &Pow) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Pow { left, right });
+        Ok(())
+    }
+
+    fn func(&mut self, node: &Func) -> VisitorResult {
+        let mut args = Vec::with_capacity(node.args.len());
+        for arg in &node.args {
+            arg.accept(self)?;
+            args.push(self.pop()?);
+        }
+
+        self.push(Func {
+            function: node.function,
+            args,
+        });
+        Ok(())
+    }
+
+    fn compare(&mut self, node: &Compare) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Compare {
+            comparison: node.comparison,
+            left,
+            right,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    /// The canonical form of `input`, as printed.
+    fn canonical(input: &str) -> String {
+        let root = parse(input, &Limits::default()).unwrap();
+        pp::to_string(canonicalize(root.as_ref()).unwrap().as_ref()).unwrap()
+    }
+
+    /// Whether `left` and `right` are equivalent.
+    fn equiv(left: &str, right: &str) -> bool {
+        let limits = Limits::default();
+        let left = parse(left, &limits).unwrap();
+        let right = parse(right, &limits).unwrap();
+        equivalent(left.as_ref(), right.as_ref(), Arithmetic::Integer, &limits).unwrap()
+    }
+
+    #[test]
+    fn selections_are_rewritten_as_the_fewest_keeps() {
+        for (input, expected) in [
+            ("4d6d1", "4d6kh3"),
+            ("4d6dl1", "4d6kh3"),
+            ("4d6kh3", "4d6kh3"),
+            ("4d6dh1", "4d6kl3"),
+            ("4d6kh3kh2", "4d6kh2"),
+            ("5d6dl1dh1", "5d6kh4kl3"),
+            ("4d6kh4", "4d6"),
+            ("4d6dl4", "4d6kh0"),
+            ("2d20kh1adv", "2d20kh1adv"),
+            ("3d6adv dl1", "3d6adv kh2"),
+            ("4d6kh5", "4d6kh5"),
+            ("4d6d1 + 1d8dl0", "4d6kh3 + 1d8"),
+        ] {
+            assert_eq!(canonical(input), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn selections_inside_a_critical_are_left_as_written() {
+        assert_eq!(canonical("crit(4d6d1)"), "crit(4d6dl1)");
+        assert_eq!(canonical("crit(4d6d1) + 4d6d1"), "crit(4d6dl1) + 4d6kh3");
+        assert_eq!(canonical("crit(4d6d1, mult2)"), "crit(4d6dl1, mult2)");
+    }
+
+    #[test]
+    fn equivalence_is_decided() {
+        assert!(equiv("4d6d1", "4d6kh3"));
+        assert!(equiv("1d6 + 1d6", "2d6"));
+        assert!(equiv("2d20kh1", "1d20adv"));
+        assert!(!equiv("2d6", "1d12"));
+        assert!(!equiv("4d6d1", "4d6kl3"));
+    }
+
+    #[test]
+    fn equivalence_under_a_critical_compares_distributions() {
+        assert!(equiv("crit(1d8kh1)", "2d8"));
+        assert!(equiv("crit(4d6d1)", "crit(4d6kh3)"));
+        assert!(!equiv("crit(4d6kh3)", "8d6kh3"));
+        assert!(!equiv("crit(4d6d1)", "crit(4d6kl3)"));
+        assert!(!equiv("crit(2d6)", "2d6"));
+        assert!(!equiv("crit(1d6, max)", "crit(1d6)"));
+    }
+}
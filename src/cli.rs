@@ -0,0 +1,477 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module defines the command-line interface: the subcommands, their
+//! options, and the help text shown for each.
+//!
+//! An expression may be given without a subcommand, in which case it is rolled
+//! as if by `roll eval`. With no arguments at all, an interactive session is
+//! started instead.
+
+use std::{
+    num::{NonZeroU64, NonZeroUsize},
+    path::PathBuf,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::eval::{Arithmetic, Evaluation, Rounding};
+use crate::export::Format;
+use crate::style::ColorChoice;
+use crate::template::Template;
+
+/// Parse and evaluate dice expressions using typical notation.
+#[derive(Parser)]
+#[command(
+    name = "roll",
+    version,
+    override_usage = "roll [OPTIONS] [EXPR]...\n       roll [--color <WHEN>] <COMMAND>"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub eval: EvalOptions,
+
+    /// When to color the output
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t = ColorMode::Auto)]
+    pub color: ColorMode,
+
+    /// Roll each line of FILE as a separate expression ('-' reads standard input)
+    #[arg(short, long, value_name = "FILE", conflicts_with_all = ["expr", "percentile", "explain", "breakdown", "strategy"])]
+    pub file: Option<PathBuf>,
+
+    /// How to write the results of a batch read with --file
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// The expression to roll ('-' alone reads expressions from standard input).
+    /// Put '--' before an expression that begins with '-'
+    #[arg(value_name = "EXPR", allow_negative_numbers = true)]
+    pub expr: Vec<String>,
+}
+
+/// The subcommands of the program.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Roll an expression and show each die and the total
+    Eval(EvalArgs),
+
+    /// Print the syntax tree of an expression as a graph
+    Graph(GraphArgs),
+
+    /// Show the exact probability of each total
+    Dist(DistArgs),
+
+    /// Show the exact mean, variance, and bounds of the total
+    Stats(DistArgs),
+
+    /// Show the exact chance that a comparison is true
+    Prob(ExprArgs),
+
+    /// Compare the totals of several expressions side by side
+    Compare(CompareArgs),
+
+    /// Decide whether two expressions have identical distributions
+    Equiv(EquivArgs),
+
+    /// Find the value of an unknown '?' that gives a target probability
+    Solve(SolveArgs),
+
+    /// Compute the expected damage per round of an attack
+    Dpr(DprArgs),
+
+    /// Draw the distribution of the total as a bar chart
+    Hist(HistArgs),
+
+    /// Estimate the distribution of the total by rolling many times
+    Sim(SimArgs),
+
+    /// Start an interactive session
+    Repl(RoundArgs),
+}
+
+/// Ways to round the exact result of an expression, as named on the command
+/// line.
+#[derive(ValueEnum, Clone, Copy)]
+pub enum RoundingMode {
+    /// Round down
+    Floor,
+
+    /// Round up
+    Ceil,
+
+    /// Round to the nearest integer, with halves rounded away from zero
+    Nearest,
+
+    /// Round to the nearest integer, with halves rounded up
+    HalfUp,
+}
+
+/// Formats for distributions and statistics.
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Human-readable text
+    Text,
+
+    /// Comma-separated values
+    Csv,
+
+    /// JSON (JSON Lines for batches)
+    Json,
+}
+
+/// When to color the output, as named on the command line.
+#[derive(ValueEnum, Clone, Copy)]
+pub enum ColorMode {
+    /// Color output written to a terminal, unless NO_COLOR is set
+    Auto,
+
+    /// Always color output
+    Always,
+
+    /// Never color output
+    Never,
+}
+
+/// Formats for syntax trees.
+#[derive(ValueEnum, Clone, Copy)]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+
+    /// Mermaid
+    Mermaid,
+}
+
+/// The rounding option shared by every subcommand that evaluates expressions.
+#[derive(Args)]
+pub struct RoundArgs {
+    /// Keep intermediate results as exact fractions and round only the total
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub round: Option<RoundingMode>,
+}
+
+/// Options for rolling an expression.
+#[derive(Args)]
+pub struct EvalOptions {
+    #[command(flatten)]
+    pub round: RoundArgs,
+
+    /// Also show where the total ranks within its distribution
+    #[arg(long)]
+    pub percentile: bool,
+
+    /// Show each step taken to evaluate the expression
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Show the expression with each roll replaced by its dice instead of a
+    /// list of dice
+    #[arg(long)]
+    pub breakdown: bool,
+
+    /// Print only the total
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = ["template", "percentile", "explain", "breakdown"]
+    )]
+    pub quiet: bool,
+
+    /// Print each result by filling in TEMPLATE, such as "{expr} => {total}".
+    /// The placeholders are {expr}, {total}, {dice}, {kept}, {dropped},
+    /// {crits}, {fumbles}, and {seed}
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        value_parser = Template::parse,
+        conflicts_with_all = ["percentile", "explain", "breakdown"]
+    )]
+    pub template: Option<Template>,
+
+    /// The seed of the random number generator, to repeat a roll
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Use the minimum result of every die instead of rolling
+    #[arg(long, group = "strategy")]
+    pub min: bool,
+
+    /// Use the maximum result of every die instead of rolling
+    #[arg(long, group = "strategy")]
+    pub max: bool,
+}
+
+#[derive(Args)]
+pub struct EvalArgs {
+    #[command(flatten)]
+    pub options: EvalOptions,
+
+    #[command(flatten)]
+    pub expr: Expr,
+}
+
+/// A single expression, which may be split across several arguments.
+#[derive(Args)]
+pub struct Expr {
+    /// The dice expression. Put '--' before an expression that begins with '-'
+    #[arg(value_name = "EXPR", required = true, allow_negative_numbers = true)]
+    pub words: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct ExprArgs {
+    #[command(flatten)]
+    pub round: RoundArgs,
+
+    #[command(flatten)]
+    pub expr: Expr,
+}
+
+#[derive(Args)]
+pub struct GraphArgs {
+    /// The graph language to write
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = GraphFormat::Dot)]
+    pub format: GraphFormat,
+
+    #[command(flatten)]
+    pub expr: Expr,
+}
+
+#[derive(Args)]
+pub struct DistArgs {
+    #[command(flatten)]
+    pub round: RoundArgs,
+
+    /// How to write the results
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub expr: Expr,
+}
+
+#[derive(Args)]
+pub struct CompareArgs {
+    #[command(flatten)]
+    pub round: RoundArgs,
+
+    /// The expressions to compare, each as a separate argument
+    #[arg(
+        value_name = "EXPR",
+        num_args = 2..=26,
+        required = true,
+        allow_negative_numbers = true
+    )]
+    pub exprs: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct EquivArgs {
+    #[command(flatten)]
+    pub round: RoundArgs,
+
+    /// The first expression
+    #[arg(value_name = "A", allow_negative_numbers = true)]
+    pub left: String,
+
+    /// The second expression
+    #[arg(value_name = "B", allow_negative_numbers = true)]
+    pub right: String,
+}
+
+#[derive(Args)]
+pub struct SolveArgs {
+    #[command(flatten)]
+    pub round: RoundArgs,
+
+    /// The desired probability, between 0 and 1
+    #[arg(short, long, visible_alias = "p", value_name = "P", value_parser = parse_probability)]
+    pub probability: f64,
+
+    /// The smallest value to try for the unknown
+    #[arg(long, value_name = "N", default_value_t = -100, allow_negative_numbers = true)]
+    pub min: i32,
+
+    /// The largest value to try for the unknown
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 100,
+        allow_negative_numbers = true
+    )]
+    pub max: i32,
+
+    #[command(flatten)]
+    pub expr: Expr,
+}
+
+#[derive(Args)]
+pub struct DprArgs {
+    #[command(flatten)]
+    pub round: RoundArgs,
+
+    /// The attack roll, which must roll a single d20
+    #[arg(long, value_name = "EXPR", allow_negative_numbers = true)]
+    pub attack: String,
+
+    /// The armor class that the attack must meet to hit
+    #[arg(long, value_name = "N", allow_negative_numbers = true)]
+    pub ac: i32,
+
+    /// Crit on a natural N or higher instead of the attack die's crit range
+    #[arg(long, value_name = "N")]
+    pub crit: Option<i32>,
+
+    /// The damage roll, whose dice are doubled on a crit
+    #[arg(long, value_name = "EXPR", allow_negative_numbers = true)]
+    pub damage: String,
+}
+
+#[derive(Args)]
+pub struct HistArgs {
+    #[command(flatten)]
+    pub round: RoundArgs,
+
+    /// Also show the chance of rolling at least and at most each total
+    #[arg(long)]
+    pub cumulative: bool,
+
+    #[command(flatten)]
+    pub expr: Expr,
+}
+
+#[derive(Args)]
+pub struct SimArgs {
+    #[command(flatten)]
+    pub round: RoundArgs,
+
+    /// The number of times to roll the expression
+    #[arg(short = 'n', long, value_name = "N", default_value = "10000")]
+    pub trials: NonZeroU64,
+
+    /// The seed of the random number generator, to repeat a simulation
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// The number of threads to use (by default, one per available core)
+    #[arg(long, value_name = "N")]
+    pub threads: Option<NonZeroUsize>,
+
+    #[command(flatten)]
+    pub expr: Expr,
+}
+
+impl Cli {
+    /// Whether any argument that only applies without a subcommand was given.
+    pub fn has_eval_args(&self) -> bool {
+        self.eval.round.round.is_some()
+            || self.eval.any()
+            || self.file.is_some()
+            || self.format != OutputFormat::Text
+            || !self.expr.is_empty()
+    }
+}
+
+impl RoundArgs {
+    /// The arithmetic selected by the rounding option.
+    pub fn arithmetic(&self) -> Arithmetic {
+        match self.round {
+            Some(mode) => Arithmetic::Exact(mode.into()),
+            None => Arithmetic::Integer,
+        }
+    }
+}
+
+impl EvalOptions {
+    /// Whether any option other than `--round` was given.
+    pub fn any(&self) -> bool {
+        self.percentile
+            || self.explain
+            || self.breakdown
+            || self.quiet
+            || self.template.is_some()
+            || self.seed.is_some()
+            || self.min
+            || self.max
+    }
+
+    /// The evaluation strategy selected by the `--min` and `--max` options,
+    /// or random rolls from a generator seeded with `seed` if neither was
+    /// given.
+    pub fn evaluation(&self, seed: u64) -> Evaluation<StdRng> {
+        if self.min {
+            Evaluation::Min
+        } else if self.max {
+            Evaluation::Max
+        } else {
+            Evaluation::Rand(StdRng::seed_from_u64(seed))
+        }
+    }
+
+    /// The seed given by `--seed`, or a random one.
+    pub fn seed(&self) -> u64 {
+        self.seed.unwrap_or_else(|| rand::thread_rng().gen())
+    }
+
+    /// The template selected by `--quiet` or `--template`, if either was given.
+    pub fn template(&self) -> Option<Template> {
+        if self.quiet {
+            Some(Template::total())
+        } else {
+            self.template.clone()
+        }
+    }
+}
+
+impl Expr {
+    /// The expression formed by joining every word. Expressions are not case
+    /// sensitive.
+    pub fn input(&self) -> String {
+        self.words.join(" ").to_lowercase()
+    }
+}
+
+impl OutputFormat {
+    /// The machine-readable format selected, or `None` for text.
+    pub fn export(self) -> Option<Format> {
+        match self {
+            OutputFormat::Text => None,
+            OutputFormat::Csv => Some(Format::Csv),
+            OutputFormat::Json => Some(Format::Json),
+        }
+    }
+}
+
+impl From<RoundingMode> for Rounding {
+    fn from(mode: RoundingMode) -> Self {
+        match mode {
+            RoundingMode::Floor => Rounding::Floor,
+            RoundingMode::Ceil => Rounding::Ceil,
+            RoundingMode::Nearest => Rounding::Nearest,
+            RoundingMode::HalfUp => Rounding::HalfUp,
+        }
+    }
+}
+
+impl From<ColorMode> for ColorChoice {
+    fn from(mode: ColorMode) -> Self {
+        match mode {
+            ColorMode::Auto => ColorChoice::Auto,
+            ColorMode::Always => ColorChoice::Always,
+            ColorMode::Never => ColorChoice::Never,
+        }
+    }
+}
+
+/// Parse a probability between 0 and 1.
+fn parse_probability(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        Ok(_) => Err("probability must be between 0 and 1".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
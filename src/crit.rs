@@ -0,0 +1,280 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module rewrites the argument of a doubled critical so that it can be
+//! evaluated like any other expression.
+//!
+//! Every roll in the argument rolls twice as many dice, and every keep or drop
+//! selects twice as many of them, so `crit(4d6kh3 + 2)` is evaluated as
+//! `8d6kh6 + 2`.
+
+use std::error::Error;
+
+use crate::ast::{
+    Add, Compare, Crit, Div, Func, Lit, Mod, Mul, Neg, Node, Pow, Roll, Select, Selection, Sub,
+    Visitor, VisitorResult,
+};
+use crate::eval::Error as EvalError;
+
+/// A rewritten sub-expression along with its value, if it is a literal.
+struct Rewritten {
+    node: Box<dyn Node>,
+    value: Option<i32>,
+}
+
+/// The rewritten operands of a binary operation.
+type Operands = (Box<dyn Node>, Box<dyn Node>);
+
+/// An implementation of the `Visitor` trait that rebuilds an expression with
+/// twice as many dice in every roll.
+pub struct Doubler {
+    /// A stack of rewritten sub-expressions. Once the traversal is complete,
+    /// the stack should contain the rewritten expression.
+    results: Vec<Rewritten>,
+}
+
+/// Rewrite `node` so that every roll rolls, keeps, and drops twice as many
+/// dice.
+pub fn double(node: &dyn Node) -> Result<Box<dyn Node>, Box<dyn Error>> {
+    let mut doubler = Doubler::new();
+    node.accept(&mut doubler)?;
+    Ok(doubler.pop()?)
+}
+
+/// Twice `count`, as a literal if it is one and doubling it does not
+/// overflow. Otherwise the product is left to be computed, and any overflow
+/// reported, when the expression is evaluated.
+fn twice(count: Rewritten) -> Box<dyn Node> {
+    match count.value.and_then(|value| value.checked_mul(2)) {
+        Some(value) => Box::new(Lit { value }),
+        None => Box::new(Mul {
+            left: count.node,
+            right: Box::new(Lit { value: 2 }),
+        }),
+    }
+}
+
+impl Doubler {
+    pub fn new() -> Self {
+        Self {
+            results: Vec::new(),
+        }
+    }
+
+    /// Pop a rewritten sub-expression from the stack.
+    fn pop(&mut self) -> Result<Box<dyn Node>, EvalError> {
+        self.pop_rewritten().map(|rewritten| rewritten.node)
+    }
+
+    /// Pop a rewritten sub-expression and its value from the stack.
+    fn pop_rewritten(&mut self) -> Result<Rewritten, EvalError> {
+        self.results.pop().ok_or(EvalError::StackUnderflow)
+    }
+
+    /// Push a rewritten sub-expression that is not a literal.
+    fn push(&mut self, node: impl Node + 'static) {
+        self.results.push(Rewritten {
+            node: Box::new(node),
+            value: None,
+        });
+    }
+
+    /// Rewrite an optional sub-expression.
+    fn rewrite(
+        &mut self,
+        node: &Option<Box<dyn Node>>,
+    ) -> Result<Option<Rewritten>, Box<dyn Error>> {
+        match node {
+            Some(node) => {
+                node.accept(self)?;
+                Ok(Some(self.pop_rewritten()?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Rewrite both operands of a binary operation.
+    fn operands(&mut self, left: &dyn Node, right: &dyn Node) -> Result<Operands, Box<dyn Error>> {
+        left.accept(self)?;
+        let left = self.pop()?;
+        right.accept(self)?;
+        let right = self.pop()?;
+        Ok((left, right))
+    }
+}
+
+impl Default for Doubler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Visitor for Doubler {
+    fn lit(&mut self, node: &Lit) -> VisitorResult {
+        self.results.push(Rewritten {
+            node: Box::new(Lit { value: node.value }),
+            value: Some(node.value),
+        });
+        Ok(())
+    }
+
+    fn roll(&mut self, node: &Roll) -> VisitorResult {
+        node.count.accept(self)?;
+        let count = self.pop_rewritten()?;
+        node.sides.accept(self)?;
+        let sides = self.pop()?;
+        let select = self.rewrite(&node.select)?;
+
+        self.push(Roll {
+            count: twice(count),
+            sides,
+            select: select.map(|select| select.node),
+            crit: node.crit,
+            fumble: node.fumble,
+        });
+        Ok(())
+    }
+
+    fn select(&mut self, node: &Select) -> VisitorResult {
+        let count = self.rewrite(&node.count)?;
+        let count = match node.selection {
+            Selection::Advantage | Selection::Disadvantage => count.map(|count| count.node),
+
+            // A keep or drop without a count selects a single die.
+            _ => Some(twice(count.unwrap_or(Rewritten {
+                node: Box::new(Lit { value: 1 }),
+                value: Some(1),
+            }))),
+        };
+        let next = self.rewrite(&node.next)?;
+
+        self.push(Select {
+            selection: node.selection,
+            count,
+            next: next.map(|next| next.node),
+        });
+        Ok(())
+    }
+
+    fn crit(&mut self, node: &Crit) -> VisitorResult {
+        node.expr.accept(self)?;
+        let expr = self.pop()?;
+        self.push(Crit::new(node.rule, expr)?);
+        Ok(())
+    }
+
+    fn neg(&mut self, node: &Neg) -> VisitorResult {
+        node.right.accept(self)?;
+        let right = self.pop()?;
+        self.push(Neg { right });
+        Ok(())
+    }
+
+    fn add(&mut self, node: &Add) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Add { left, right });
+        Ok(())
+    }
+
+    fn sub(&mut self, node: &Sub) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Sub { left, right });
+        Ok(())
+    }
+
+    fn mul(&mut self, node: &Mul) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Mul { left, right });
+        Ok(())
+    }
+
+    fn div(&mut self, node: &Div) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Div { left, right });
+        Ok(())
+    }
+
+    fn modulo(&mut self, node: &Mod) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Mod { left, right });
+        Ok(())
+    }
+
+    fn pow(&mut self, node: &Pow) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Pow { left, right });
+        Ok(())
+    }
+
+    fn func(&mut self, node: &Func) -> VisitorResult {
+        let mut args = Vec::with_capacity(node.args.len());
+        for arg in &node.args {
+            arg.accept(self)?;
+            args.push(self.pop()?);
+        }
+
+        self.push(Func {
+            function: node.function,
+            args,
+        });
+        Ok(())
+    }
+
+    fn compare(&mut self, node: &Compare) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+        self.push(Compare {
+            comparison: node.comparison,
+            left,
+            right,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+    use crate::dist::DistEvaluator;
+    use crate::eval::{Evaluation, Evaluator};
+    use crate::limits::Limits;
+    use crate::parser::parse;
+
+    /// Whether `left` and `right` have the same distribution.
+    fn same_distribution(left: &str, right: &str) -> bool {
+        let limits = Limits::default();
+        let mut evaluator = DistEvaluator::new();
+        let left = evaluator.eval(parse(left, &limits).unwrap().as_ref());
+        let right = evaluator.eval(parse(right, &limits).unwrap().as_ref());
+        left.unwrap().outcomes == right.unwrap().outcomes
+    }
+
+    #[test]
+    fn doubling_rolls_and_selects_twice_as_many_dice() {
+        for (crit, doubled) in [
+            ("crit(2d6 + 4)", "4d6 + 4"),
+            ("crit(1d8kh1)", "2d8"),
+            ("crit(4d6kh3)", "8d6kh6"),
+            ("crit(4d6d1)", "8d6dl2"),
+            ("crit(3d6kh2kl1)", "6d6kh4kl2"),
+            ("crit(1d20adv)", "2d20adv"),
+            ("crit(1d6 * 2d4)", "2d6 * 4d4"),
+            ("crit(crit(1d6))", "4d6"),
+            ("crit(crit(1d6, max))", "2d6 + 12"),
+            ("crit(crit(1d4, mult2))", "2d4 + 2d4"),
+        ] {
+            assert!(same_distribution(crit, doubled), "{crit} is not {doubled}");
+        }
+
+        assert!(!same_distribution("crit(4d6kh3)", "8d6kh3"));
+    }
+
+    #[test]
+    fn doubling_a_huge_count_overflows_when_evaluated() {
+        let root = parse("crit(2000000000d6)", &Limits::default()).unwrap();
+        let mut evaluator = Evaluator::<StepRng>::new(Evaluation::Max);
+        let err = evaluator.eval(root.as_ref()).unwrap_err();
+        assert_eq!(err.to_string(), EvalError::Overflow.to_string());
+    }
+}
@@ -0,0 +1,1125 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module contains an evaluator that traverses an AST and computes the
+//! exact probability distribution of the expression's total by convolving the
+//! distributions of its sub-expressions.
+
+use std::{
+    collections::BTreeMap,
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult},
+    ops::Range,
+};
+
+use num_bigint::BigUint;
+use num_rational::{BigRational, Rational64};
+use num_traits::{One, ToPrimitive, Zero};
+
+use crate::ast::{
+    Add, Compare, Comparison, Crit, CritRule, Div, Func, Lit, Mod, Mul, Neg, Node, Pow, Roll,
+    Select, Selection, Sub, Threshold, Visitor, VisitorResult,
+};
+use crate::eval::{Arithmetic, Error as EvalError};
+use crate::limits::Limits;
+
+/// The most work (roughly, the number of weights multiplied together) that may
+/// be done computing a single distribution.
+const MAX_WORK: usize = 20_000_000;
+
+/// The exact probability distribution of the total of a dice expression.
+pub struct Distribution {
+    /// The probability of each possible total. Totals that cannot occur are
+    /// omitted.
+    pub outcomes: BTreeMap<i32, BigRational>,
+}
+
+/// The probability of one total of a distribution along with the chance of
+/// rolling at least or at most that total.
+pub struct Cumulative<'d> {
+    pub outcome: i32,
+    pub probability: &'d BigRational,
+    pub at_least: BigRational,
+    pub at_most: BigRational,
+}
+
+/// A probability mass function, given as integer weights out of a common
+/// total so that no fractions need to be reduced until the end.
+#[derive(Clone)]
+struct Pmf<T: Ord> {
+    weights: BTreeMap<T, BigUint>,
+    total: BigUint,
+}
+
+/// The dice remaining in the pool of a roll that is being selected from.
+enum Pool {
+    /// The dice at a range of positions, counting from the highest result,
+    /// among `count` dice with `sides` sides each. Keeping and dropping dice
+    /// only narrows the range, so the distribution of the total can be found
+    /// without enumerating every possible pool.
+    Window {
+        count: usize,
+        sides: i32,
+        kept: Range<usize>,
+    },
+
+    /// The distribution of the remaining dice, each sorted from highest to
+    /// lowest result. This is needed once the dice are rerolled.
+    Dice { sides: i32, dice: Pmf<Vec<i32>> },
+}
+
+/// The attack die of an expression: the first roll of d20s that keeps a
+/// single die, whose natural result decides whether an attack hits or crits.
+pub struct AttackDie {
+    /// The probability that the die shows the natural result that was assumed.
+    pub chance: BigRational,
+
+    /// Natural results that count as a critical hit.
+    pub crit: Threshold,
+
+    /// Natural results that count as a critical miss.
+    pub fumble: Threshold,
+}
+
+/// An implementation of the `Visitor` trait that computes the exact probability
+/// distribution of an expression using a stack of distributions.
+pub struct DistEvaluator {
+    /// How intermediate results are computed. Defaults to `Arithmetic::Integer`.
+    pub arithmetic: Arithmetic,
+
+    /// Limits on the dice and repetitions in the expression.
+    pub limits: Limits,
+
+    /// If given, the attack die is assumed to show this natural result instead
+    /// of being rolled, so that the rest of an attack can be evaluated given
+    /// that result. The attack die that was found is then available from
+    /// `attack_die`.
+    pub natural: Option<i32>,
+
+    /// The attack die found while evaluating with an assumed natural result.
+    attack_die: Option<AttackDie>,
+
+    /// A stack of intermediate distributions. Once the traversal is complete,
+    /// the stack should contain the distribution of the expression.
+    results: Vec<Pmf<Rational64>>,
+
+    /// A stack of dice pools that are being selected from.
+    pools: Vec<Pool>,
+
+    /// Whether a maximized critical applies to rolls in the sub-expression
+    /// currently being evaluated. Other rules need no state: a doubled
+    /// critical is rewritten when it is parsed, and a multiplied one repeats
+    /// its whole expression.
+    maximize: bool,
+
+    /// The work done so far, which is limited to `MAX_WORK`.
+    work: usize,
+}
+
+/// Possible errors that can occur when computing a distribution.
+#[derive(Debug)]
+pub enum Error {
+    /// The number of dice or sides in a roll, or the number of dice selected,
+    /// depends on the result of another roll.
+    VariableCount,
+
+    /// Computing the distribution would take too long.
+    TooComplex,
+
+    /// The probability of an expression was requested, but it can have a
+    /// result other than true (1) or false (0).
+    NotBoolean,
+}
+
+impl Distribution {
+    /// The cumulative probabilities of each total, in increasing order.
+    pub fn cumulative(&self) -> Vec<Cumulative<'_>> {
+        let mut at_least: BigRational = self.outcomes.values().sum();
+        let mut at_most = BigRational::zero();
+
+        self.outcomes
+            .iter()
+            .map(|(&outcome, probability)| {
+                at_most += probability;
+                let row = Cumulative {
+                    outcome,
+                    probability,
+                    at_least: at_least.clone(),
+                    at_most: at_most.clone(),
+                };
+                at_least -= probability;
+                row
+            })
+            .collect()
+    }
+
+    /// The probability that an expression with a true or false result, such
+    /// as a comparison, is true.
+    pub fn probability(&self) -> Result<BigRational, Error> {
+        if self
+            .outcomes
+            .keys()
+            .any(|&outcome| outcome != 0 && outcome != 1)
+        {
+            return Err(Error::NotBoolean);
+        }
+
+        Ok(self
+            .outcomes
+            .get(&1)
+            .cloned()
+            .unwrap_or_else(BigRational::zero))
+    }
+
+    /// The probability of rolling a total lower than `total`.
+    pub fn below(&self, total: i32) -> BigRational {
+        self.outcomes.range(..total).map(|(_, p)| p).sum()
+    }
+
+    /// The percentile rank of `total` as a fraction: the probability of
+    /// rolling lower plus half the probability of rolling exactly `total`.
+    pub fn percentile(&self, total: i32) -> BigRational {
+        let equal = self
+            .outcomes
+            .get(&total)
+            .cloned()
+            .unwrap_or_else(BigRational::zero);
+
+        self.below(total) + equal / BigRational::from_integer(2.into())
+    }
+
+    /// The probability that a total drawn from this distribution is greater
+    /// than an independent total drawn from `other`.
+    pub fn probability_greater(&self, other: &Distribution) -> BigRational {
+        let mut probability = BigRational::zero();
+        let mut below = BigRational::zero();
+        let mut others = other.outcomes.iter().peekable();
+
+        // Walk both distributions in increasing order, accumulating the chance
+        // that the other total is below each of this distribution's totals.
+        for (outcome, p) in &self.outcomes {
+            while let Some((_, q)) = others.next_if(|(other, _)| *other < outcome) {
+                below += q;
+            }
+
+            probability += p * &below;
+        }
+
+        probability
+    }
+}
+
+impl<T: Ord + Clone> Pmf<T> {
+    /// A distribution with a single certain outcome.
+    fn certain(value: T) -> Self {
+        Self {
+            weights: BTreeMap::from([(value, BigUint::one())]),
+            total: BigUint::one(),
+        }
+    }
+
+    /// The only possible outcome, if the distribution has exactly one.
+    fn constant(&self) -> Option<&T> {
+        match self.weights.len() {
+            1 => self.weights.keys().next(),
+            _ => None,
+        }
+    }
+}
+
+impl Pmf<Rational64> {
+    /// The distribution of the total of `count` dice with `sides` sides each.
+    fn dice(count: i32, sides: i32, work: &mut usize) -> Result<Self, Error> {
+        let mut weights = vec![BigUint::one()];
+
+        for _ in 0..count {
+            add_work(work, weights.len() * sides as usize)?;
+
+            let mut next = vec![BigUint::zero(); weights.len() + sides as usize - 1];
+            for (i, weight) in weights.iter().enumerate() {
+                for slot in &mut next[i..i + sides as usize] {
+                    *slot += weight;
+                }
+            }
+
+            weights = next;
+        }
+
+        Ok(Self {
+            weights: weights
+                .into_iter()
+                .enumerate()
+                .map(|(i, weight)| (Rational64::from_integer(i as i64 + count as i64), weight))
+                .collect(),
+            total: BigUint::from(sides as u32).pow(count as u32),
+        })
+    }
+
+    /// The distribution of the total of the dice at the sorted positions `kept`
+    /// (counting from the highest result) among `count` dice with `sides`
+    /// sides each.
+    ///
+    /// Rather than enumerating every pool, results are assigned from the
+    /// highest down, tracking only how many dice have been assigned so far and
+    /// the total of those that landed in `kept`. This takes time polynomial in
+    /// the number of dice and sides.
+    fn order_statistics(
+        count: usize,
+        sides: i32,
+        kept: Range<usize>,
+        work: &mut usize,
+    ) -> Result<Self, Error> {
+        let max_total = kept.len() * sides as usize;
+
+        // `states[j][t]` is the number of ways that the `j` highest dice can
+        // have results above the current one with the kept dice among them
+        // totalling `t`.
+        let mut states = vec![Vec::<BigUint>::new(); count + 1];
+        states[0] = vec![BigUint::zero(); max_total + 1];
+        states[0][0] = BigUint::one();
+        let mut finished = vec![BigUint::zero(); max_total + 1];
+
+        for result in (1..=sides).rev() {
+            // Once every kept die has been assigned, the rest may have any
+            // result up to the current one.
+            for (j, totals) in states.iter_mut().enumerate().skip(kept.end) {
+                if totals.is_empty() {
+                    continue;
+                }
+
+                add_work(work, totals.len())?;
+                let ways = BigUint::from(result as u32).pow((count - j) as u32);
+                for (t, weight) in std::mem::take(totals).into_iter().enumerate() {
+                    finished[t] += weight * &ways;
+                }
+            }
+
+            let mut next = vec![Vec::<BigUint>::new(); count + 1];
+            for (j, totals) in states.iter().enumerate() {
+                if totals.is_empty() {
+                    continue;
+                }
+
+                // Choose how many of the remaining dice show this result. The
+                // lowest result must be shown by all of them.
+                let remaining = count - j;
+                let mut ways = BigUint::one();
+                for shown in 0..=remaining {
+                    if shown > 0 {
+                        ways = ways * (remaining - shown + 1) / shown;
+                    }
+
+                    if result == 1 && shown < remaining {
+                        continue;
+                    }
+
+                    add_work(work, totals.len())?;
+                    let overlap = (j + shown).min(kept.end).saturating_sub(j.max(kept.start));
+                    let offset = overlap * result as usize;
+
+                    let target = &mut next[j + shown];
+                    if target.is_empty() {
+                        *target = vec![BigUint::zero(); max_total + 1];
+                    }
+
+                    for (t, weight) in totals.iter().enumerate() {
+                        if !weight.is_zero() {
+                            target[t + offset] += weight * &ways;
+                        }
+                    }
+                }
+            }
+
+            states = next;
+        }
+
+        for (t, weight) in std::mem::take(&mut states[count]).into_iter().enumerate() {
+            finished[t] += weight;
+        }
+
+        Ok(Self {
+            weights: finished
+                .into_iter()
+                .enumerate()
+                .filter(|(_, weight)| !weight.is_zero())
+                .map(|(t, weight)| (Rational64::from_integer(t as i64), weight))
+                .collect(),
+            total: BigUint::from(sides as u32).pow(count as u32),
+        })
+    }
+
+    /// Combine two independent distributions by applying `op` to every pair
+    /// of their outcomes.
+    fn combine(
+        &self,
+        other: &Self,
+        work: &mut usize,
+        op: impl Fn(Rational64, Rational64) -> Result<Rational64, EvalError>,
+    ) -> Result<Self, Box<dyn StdError>> {
+        add_work(work, self.weights.len() * other.weights.len())?;
+
+        let mut weights = BTreeMap::new();
+        for (left, left_weight) in &self.weights {
+            for (right, right_weight) in &other.weights {
+                *weights
+                    .entry(op(*left, *right)?)
+                    .or_insert_with(BigUint::zero) += left_weight * right_weight;
+            }
+        }
+
+        Ok(Self {
+            weights,
+            total: &self.total * &other.total,
+        })
+    }
+
+    /// Apply `op` to every outcome of the distribution.
+    fn map(
+        &self,
+        op: impl Fn(Rational64) -> Result<Rational64, EvalError>,
+    ) -> Result<Self, EvalError> {
+        let mut weights = BTreeMap::new();
+        for (value, weight) in &self.weights {
+            *weights.entry(op(*value)?).or_insert_with(BigUint::zero) += weight;
+        }
+
+        Ok(Self {
+            weights,
+            total: self.total.clone(),
+        })
+    }
+}
+
+impl Pool {
+    /// A pool of `count` dice with `sides` sides each.
+    fn roll(count: usize, sides: i32) -> Self {
+        Pool::Window {
+            count,
+            sides,
+            kept: 0..count,
+        }
+    }
+
+    /// The number of dice in the pool.
+    fn len(&self) -> usize {
+        match self {
+            Pool::Window { kept, .. } => kept.len(),
+            Pool::Dice { dice, .. } => dice.weights.keys().next().map_or(0, |dice| dice.len()),
+        }
+    }
+
+    /// Keep or drop the highest or lowest `count` dice of every pool.
+    fn select(self, selection: Selection, count: usize) -> Self {
+        match self {
+            Pool::Window {
+                count: total,
+                sides,
+                kept,
+            } => {
+                let kept = match selection {
+                    Selection::KeepHighest => kept.start..kept.start + count,
+                    Selection::KeepLowest => kept.end - count..kept.end,
+                    Selection::DropHighest => kept.start + count..kept.end,
+                    Selection::DropLowest => kept.start..kept.end - count,
+                    Selection::Advantage | Selection::Disadvantage => kept,
+                };
+
+                Pool::Window {
+                    count: total,
+                    sides,
+                    kept,
+                }
+            }
+
+            Pool::Dice { sides, dice } => {
+                let mut weights = BTreeMap::new();
+                for (dice, weight) in dice.weights {
+                    let remaining = match selection {
+                        Selection::KeepHighest => &dice[..count],
+                        Selection::KeepLowest => &dice[dice.len() - count..],
+                        Selection::DropHighest => &dice[count..],
+                        Selection::DropLowest => &dice[..dice.len() - count],
+                        Selection::Advantage | Selection::Disadvantage => &dice,
+                    };
+
+                    *weights
+                        .entry(remaining.to_vec())
+                        .or_insert_with(BigUint::zero) += weight;
+                }
+
+                Pool::Dice {
+                    sides,
+                    dice: Pmf {
+                        weights,
+                        total: dice.total,
+                    },
+                }
+            }
+        }
+    }
+
+    /// The number of sides of each die and the distribution of every sorted
+    /// pool of remaining dice.
+    fn enumerate(self, work: &mut usize) -> Result<(i32, Pmf<Vec<i32>>), Error> {
+        match self {
+            Pool::Window { count, sides, kept } => {
+                let mut dice = BTreeMap::new();
+                let mut results = Vec::with_capacity(count);
+                enumerate_pools(count, sides, &mut results, &mut dice, work)?;
+
+                let mut weights = BTreeMap::new();
+                for (dice, weight) in dice {
+                    *weights
+                        .entry(dice[kept.clone()].to_vec())
+                        .or_insert_with(BigUint::zero) += weight;
+                }
+
+                let total = BigUint::from(sides as u32).pow(count as u32);
+                Ok((sides, Pmf { weights, total }))
+            }
+
+            Pool::Dice { sides, dice } => Ok((sides, dice)),
+        }
+    }
+
+    /// Reroll every pool and keep either the original or the reroll in the
+    /// same way as `Evaluator`: with advantage, the reroll is kept only if its
+    /// total is higher, and with disadvantage, only if it is not higher.
+    fn reroll(self, advantage: bool, work: &mut usize) -> Result<Self, Error> {
+        let len = self.len();
+        let (sides, dice) = self.enumerate(work)?;
+        let (_, rerolls) = Pool::roll(len, sides).enumerate(work)?;
+        add_work(work, dice.weights.len() * rerolls.weights.len())?;
+
+        let mut weights = BTreeMap::new();
+        for (old, old_weight) in &dice.weights {
+            let old_total: i32 = old.iter().sum();
+
+            for (new, new_weight) in &rerolls.weights {
+                let new_total: i32 = new.iter().sum();
+                let kept = if (new_total > old_total) == advantage {
+                    new
+                } else {
+                    old
+                };
+
+                *weights.entry(kept.clone()).or_insert_with(BigUint::zero) +=
+                    old_weight * new_weight;
+            }
+        }
+
+        Ok(Pool::Dice {
+            sides,
+            dice: Pmf {
+                weights,
+                total: &dice.total * &rerolls.total,
+            },
+        })
+    }
+
+    /// The distribution of the total of the remaining dice.
+    fn total(self, work: &mut usize) -> Result<Pmf<Rational64>, Error> {
+        match self {
+            Pool::Window { count, sides, kept } => Pmf::order_statistics(count, sides, kept, work),
+
+            Pool::Dice { dice, .. } => {
+                let mut weights = BTreeMap::new();
+                for (dice, weight) in dice.weights {
+                    let total = dice.iter().map(|&result| i64::from(result)).sum();
+                    *weights
+                        .entry(Rational64::from_integer(total))
+                        .or_insert_with(BigUint::zero) += weight;
+                }
+
+                Ok(Pmf {
+                    weights,
+                    total: dice.total,
+                })
+            }
+        }
+    }
+}
+
+/// Add to the work done so far, failing if it exceeds `MAX_WORK`.
+fn add_work(work: &mut usize, amount: usize) -> Result<(), Error> {
+    *work = work.saturating_add(amount);
+    if *work > MAX_WORK {
+        return Err(Error::TooComplex);
+    }
+
+    Ok(())
+}
+
+/// Enumerate every sorted sequence of `count` more results no higher than
+/// `sides` that can follow `results`, weighted by the number of orders in
+/// which the dice could have been rolled to produce it.
+fn enumerate_pools(
+    count: usize,
+    sides: i32,
+    results: &mut Vec<i32>,
+    pools: &mut BTreeMap<Vec<i32>, BigUint>,
+    work: &mut usize,
+) -> Result<(), Error> {
+    if count == 0 {
+        add_work(work, results.len())?;
+
+        // The number of orders is the multinomial coefficient of the number
+        // of times each result appears.
+        let mut weight = BigUint::one();
+        let mut run = 0u32;
+        for (i, result) in results.iter().enumerate() {
+            run = if i > 0 && results[i - 1] == *result {
+                run + 1
+            } else {
+                1
+            };
+            weight = weight * (i as u32 + 1) / run;
+        }
+
+        pools.insert(results.clone(), weight);
+        return Ok(());
+    }
+
+    for result in (1..=sides).rev() {
+        if results.last().is_some_and(|&last| result > last) {
+            continue;
+        }
+
+        results.push(result);
+        enumerate_pools(count - 1, sides, results, pools, work)?;
+        results.pop();
+    }
+
+    Ok(())
+}
+
+impl DistEvaluator {
+    pub fn new() -> Self {
+        Self {
+            arithmetic: Arithmetic::Integer,
+            limits: Limits::default(),
+            results: Vec::new(),
+            pools: Vec::new(),
+            maximize: false,
+            work: 0,
+            natural: None,
+            attack_die: None,
+        }
+    }
+
+    /// The attack die found by the last evaluation, if a natural result was
+    /// assumed and the expression has an attack die.
+    pub fn attack_die(&self) -> Option<&AttackDie> {
+        self.attack_die.as_ref()
+    }
+
+    pub fn eval(&mut self, node: &dyn Node) -> Result<Distribution, Box<dyn StdError>> {
+        self.results.clear();
+        self.pools.clear();
+        self.maximize = false;
+        self.work = 0;
+        self.attack_die = None;
+        node.accept(self)?;
+
+        let pmf = self.results.pop().ok_or(EvalError::StackUnderflow)?;
+        let total = BigRational::from_integer(pmf.total.into());
+
+        let mut outcomes = BTreeMap::new();
+        for (value, weight) in pmf.weights {
+            let probability = BigRational::from_integer(weight.into()) / &total;
+            *outcomes
+                .entry(self.arithmetic.total(value)?)
+                .or_insert_with(BigRational::zero) += probability;
+        }
+
+        Ok(Distribution { outcomes })
+    }
+
+    /// Pop an intermediate distribution from the stack.
+    fn pop(&mut self) -> Result<Pmf<Rational64>, EvalError> {
+        self.results.pop().ok_or(EvalError::StackUnderflow)
+    }
+
+    /// Pop an intermediate distribution that is used as a count, such as the
+    /// number of dice to roll, and so must have a single non-negative integer
+    /// outcome.
+    fn pop_count(&mut self) -> Result<i32, Box<dyn StdError>> {
+        let pmf = self.pop()?;
+        let Some(count) = pmf.constant() else {
+            return Err(Box::new(Error::VariableCount));
+        };
+
+        let count = count
+            .floor()
+            .to_integer()
+            .to_i32()
+            .ok_or(EvalError::Overflow)?;
+        if count < 0 {
+            return Err(Box::new(EvalError::NegativeCount(count)));
+        }
+
+        Ok(count)
+    }
+
+    /// Evaluate the left and right operands of a binary operation and push the
+    /// distribution of `op` applied to them.
+    fn binary(
+        &mut self,
+        left: &dyn Node,
+        right: &dyn Node,
+        op: impl Fn(Rational64, Rational64) -> Result<Rational64, EvalError>,
+    ) -> VisitorResult {
+        left.accept(self)?;
+        let left = self.pop()?;
+        right.accept(self)?;
+        let right = self.pop()?;
+
+        let result = left.combine(&right, &mut self.work, op)?;
+        self.results.push(result);
+        Ok(())
+    }
+}
+
+impl Default for DistEvaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Visitor for DistEvaluator {
+    fn lit(&mut self, node: &Lit) -> VisitorResult {
+        self.results
+            .push(Pmf::certain(Rational64::from_integer(node.value.into())));
+        Ok(())
+    }
+
+    fn roll(&mut self, node: &Roll) -> VisitorResult {
+        node.count.accept(self)?;
+        let count = self.pop_count()?;
+
+        node.sides.accept(self)?;
+        let sides = self.pop_count()?;
+
+        if count as usize > self.limits.max_dice {
+            return Err(Box::new(EvalError::TooManyDice(self.limits.max_dice)));
+        }
+
+        let (pmf, kept) = match &node.select {
+            None => (Pmf::dice(count, sides, &mut self.work)?, count),
+            Some(select) => {
+                self.pools.push(Pool::roll(count as usize, sides));
+                select.accept(self)?;
+                let pool = self.pools.pop().ok_or(EvalError::StackUnderflow)?;
+
+                let kept = pool.len() as i32;
+                (pool.total(&mut self.work)?, kept)
+            }
+        };
+
+        // The first single d20 is the attack die if a natural result is being
+        // assumed.
+        let pmf = match self.natural {
+            Some(natural) if sides == 20 && kept == 1 && self.attack_die.is_none() => {
+                let weight = pmf
+                    .weights
+                    .get(&Rational64::from_integer(natural.into()))
+                    .cloned()
+                    .unwrap_or_else(BigUint::zero);
+
+                self.attack_die = Some(AttackDie {
+                    chance: BigRational::new(weight.into(), pmf.total.into()),
+                    crit: node.crit.unwrap_or(Threshold {
+                        comparison: Comparison::Equal,
+                        value: 20,
+                    }),
+                    fumble: node.fumble.unwrap_or(Threshold {
+                        comparison: Comparison::Equal,
+                        value: 1,
+                    }),
+                });
+
+                Pmf::certain(Rational64::from_integer(natural.into()))
+            }
+            _ => pmf,
+        };
+
+        // A maximized critical adds the highest result of each kept die.
+        let bonus = if self.maximize {
+            i64::from(kept) * i64::from(sides)
+        } else {
+            0
+        };
+        let bonus = Rational64::from_integer(bonus);
+
+        let pmf = pmf.map(|total| self.arithmetic.add(total, bonus))?;
+        self.results.push(pmf);
+        Ok(())
+    }
+
+    fn select(&mut self, node: &Select) -> VisitorResult {
+        let Some(pool) = self.pools.pop() else {
+            return Err(Box::new(EvalError::StackUnderflow));
+        };
+
+        let pool = match node.selection {
+            Selection::KeepHighest
+            | Selection::KeepLowest
+            | Selection::DropHighest
+            | Selection::DropLowest => {
+                let count = match &node.count {
+                    Some(child) => {
+                        child.accept(self)?;
+                        self.pop_count()? as usize
+                    }
+                    None => 1,
+                };
+
+                if count > pool.len() {
+                    return Err(Box::new(EvalError::InvalidSelection {
+                        selection_size: count,
+                        pool_size: pool.len(),
+                    }));
+                }
+
+                pool.select(node.selection, count)
+            }
+
+            Selection::Advantage => pool.reroll(true, &mut self.work)?,
+            Selection::Disadvantage => pool.reroll(false, &mut self.work)?,
+        };
+
+        self.pools.push(pool);
+
+        if let Some(next) = &node.next {
+            next.accept(self)?;
+        }
+
+        Ok(())
+    }
+
+    fn crit(&mut self, node: &Crit) -> VisitorResult {
+        let outer = std::mem::replace(&mut self.maximize, node.rule == CritRule::Maximize);
+        let result = node.operand().accept(self);
+        self.maximize = outer;
+        result?;
+
+        // Each repetition of a multiplied critical is independent, so the
+        // distribution is that of the sum of as many copies.
+        if let CritRule::Multiply(times) = node.rule {
+            if times as usize > self.limits.max_repetitions {
+                return Err(Box::new(EvalError::TooManyRepetitions(
+                    self.limits.max_repetitions,
+                )));
+            }
+
+            let once = self.pop()?;
+            let mut sum = once.clone();
+            for _ in 1..times {
+                sum = sum.combine(&once, &mut self.work, |left, right| {
+                    self.arithmetic.add(left, right)
+                })?;
+            }
+
+            self.results.push(sum);
+        }
+
+        Ok(())
+    }
+
+    fn neg(&mut self, node: &Neg) -> VisitorResult {
+        node.right.accept(self)?;
+        let right = self.pop()?;
+        self.results
+            .push(right.map(|right| self.arithmetic.neg(right))?);
+        Ok(())
+    }
+
+    fn add(&mut self, node: &Add) -> VisitorResult {
+        let arithmetic = self.arithmetic;
+        self.binary(node.left.as_ref(), node.right.as_ref(), |l, r| {
+            arithmetic.add(l, r)
+        })
+    }
+
+    fn sub(&mut self, node: &Sub) -> VisitorResult {
+        let arithmetic = self.arithmetic;
+        self.binary(node.left.as_ref(), node.right.as_ref(), |l, r| {
+            arithmetic.sub(l, r)
+        })
+    }
+
+    fn mul(&mut self, node: &Mul) -> VisitorResult {
+        let arithmetic = self.arithmetic;
+        self.binary(node.left.as_ref(), node.right.as_ref(), |l, r| {
+            arithmetic.mul(l, r)
+        })
+    }
+
+    fn div(&mut self, node: &Div) -> VisitorResult {
+        let arithmetic = self.arithmetic;
+        self.binary(node.left.as_ref(), node.right.as_ref(), |l, r| {
+            arithmetic.div(l, r)
+        })
+    }
+
+    fn modulo(&mut self, node: &Mod) -> VisitorResult {
+        let arithmetic = self.arithmetic;
+        self.binary(node.left.as_ref(), node.right.as_ref(), |l, r| {
+            arithmetic.modulo(l, r)
+        })
+    }
+
+    fn pow(&mut self, node: &Pow) -> VisitorResult {
+        let arithmetic = self.arithmetic;
+        self.binary(node.left.as_ref(), node.right.as_ref(), |l, r| {
+            arithmetic.pow(l, r)
+        })
+    }
+
+    fn func(&mut self, node: &Func) -> VisitorResult {
+        // Build the distribution of every combination of arguments, then apply
+        // the function to each one.
+        let mut args = Pmf::certain(Vec::new());
+        for arg in &node.args {
+            arg.accept(self)?;
+            let arg = self.pop()?;
+            add_work(&mut self.work, args.weights.len() * arg.weights.len())?;
+
+            let mut weights = BTreeMap::new();
+            for (values, values_weight) in &args.weights {
+                for (value, value_weight) in &arg.weights {
+                    let mut values = values.clone();
+                    values.push(*value);
+                    *weights.entry(values).or_insert_with(BigUint::zero) +=
+                        values_weight * value_weight;
+                }
+            }
+
+            args = Pmf {
+                weights,
+                total: &args.total * &arg.total,
+            };
+        }
+
+        let mut weights = BTreeMap::new();
+        for (values, weight) in args.weights {
+            let value = self.arithmetic.func(node.function, &values)?;
+            *weights.entry(value).or_insert_with(BigUint::zero) += weight;
+        }
+
+        self.results.push(Pmf {
+            weights,
+            total: args.total,
+        });
+        Ok(())
+    }
+
+    fn compare(&mut self, node: &Compare) -> VisitorResult {
+        let comparison = node.comparison;
+        self.binary(node.left.as_ref(), node.right.as_ref(), |l, r| {
+            Ok(Rational64::from_integer(comparison.test(l, r).into()))
+        })
+    }
+}
+
+impl StdError for Error {}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Error::VariableCount => write!(
+                f,
+                "The number of dice and sides must not depend on another roll"
+            ),
+            Error::TooComplex => write!(f, "Distribution is too complex to compute"),
+            Error::NotBoolean => write!(
+                f,
+                "Expression must be a comparison with a true or false result"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    /// Compute the distribution of the total of the dice at the sorted
+    /// positions `kept` by rolling every ordered combination of dice.
+    fn brute_force(count: usize, sides: i32, kept: Range<usize>) -> Pmf<Rational64> {
+        let mut weights = BTreeMap::new();
+        let mut dice = vec![1; count];
+
+        loop {
+            let mut sorted = dice.clone();
+            sorted.sort_unstable_by(|a, b| b.cmp(a));
+            let total: i32 = sorted[kept.clone()].iter().sum();
+            *weights
+                .entry(Rational64::from_integer(total.into()))
+                .or_insert_with(BigUint::zero) += BigUint::one();
+
+            // Advance to the next combination like an odometer.
+            let Some(i) = dice.iter().position(|&result| result < sides) else {
+                break;
+            };
+            dice[i] += 1;
+            dice[..i].fill(1);
+        }
+
+        Pmf {
+            weights,
+            total: BigUint::from(sides as u32).pow(count as u32),
+        }
+    }
+
+    /// Compute the distribution of `total` over every ordered combination of
+    /// dice with the given numbers of `sides`.
+    fn brute_force_outcomes(
+        sides: &[i32],
+        total: impl Fn(&[i32]) -> i32,
+    ) -> BTreeMap<i32, BigRational> {
+        let mut counts = BTreeMap::new();
+        let mut dice = vec![1; sides.len()];
+        let combinations: i64 = sides.iter().map(|&sides| i64::from(sides)).product();
+
+        loop {
+            *counts.entry(total(&dice)).or_insert(0) += 1;
+
+            let Some(i) = (0..dice.len()).find(|&i| dice[i] < sides[i]) else {
+                break;
+            };
+            dice[i] += 1;
+            dice[..i].fill(1);
+        }
+
+        counts
+            .into_iter()
+            .map(|(outcome, count): (i32, i64)| {
+                let probability = BigRational::new(count.into(), combinations.into());
+                (outcome, probability)
+            })
+            .collect()
+    }
+
+    /// The distribution of `input`.
+    fn outcomes(input: &str) -> BTreeMap<i32, BigRational> {
+        let root = parse(input, &Limits::default()).unwrap();
+        DistEvaluator::new().eval(root.as_ref()).unwrap().outcomes
+    }
+
+    #[test]
+    fn expressions_match_brute_force() {
+        // With advantage, the reroll is kept only if it is higher.
+        assert_eq!(
+            outcomes("1d20adv"),
+            brute_force_outcomes(&[20, 20], |dice| dice[0].max(dice[1]))
+        );
+
+        // With disadvantage, the reroll is kept unless its total is higher.
+        assert_eq!(
+            outcomes("2d6dis"),
+            brute_force_outcomes(&[6, 6, 6, 6], |dice| {
+                (dice[0] + dice[1]).min(dice[2] + dice[3])
+            })
+        );
+
+        assert_eq!(
+            outcomes("crit(1d6)"),
+            brute_force_outcomes(&[6, 6], |dice| dice[0] + dice[1])
+        );
+        assert_eq!(
+            outcomes("crit(1d6, max) + 1"),
+            brute_force_outcomes(&[6], |dice| dice[0] + 7)
+        );
+        assert_eq!(
+            outcomes("min(1d6, 1d4)"),
+            brute_force_outcomes(&[6, 4], |dice| dice[0].min(dice[1]))
+        );
+        assert_eq!(
+            outcomes("max(1d4, 1d6 - 2, 2)"),
+            brute_force_outcomes(&[4, 6], |dice| dice[0].max(dice[1] - 2).max(2))
+        );
+        assert_eq!(
+            outcomes("min(2d4) + max(1d8kh1, 3)"),
+            brute_force_outcomes(&[4, 4, 8], |dice| dice[0] + dice[1] + dice[2].max(3))
+        );
+    }
+
+    #[test]
+    fn comparisons_are_zero_or_one() {
+        let ratio = |numer: i32, denom: i32| BigRational::new(numer.into(), denom.into());
+        for (input, expected) in [
+            ("1d6 >= 5", vec![(0, ratio(2, 3)), (1, ratio(1, 3))]),
+            ("1d6 > 5", vec![(0, ratio(5, 6)), (1, ratio(1, 6))]),
+            (
+                "1d20 + 5 >= 15",
+                vec![(0, ratio(9, 20)), (1, ratio(11, 20))],
+            ),
+            ("2d6 = 7", vec![(0, ratio(5, 6)), (1, ratio(1, 6))]),
+            ("1d4 < 1d4", vec![(0, ratio(5, 8)), (1, ratio(3, 8))]),
+            ("1d4 <= 4", vec![(1, ratio(1, 1))]),
+            ("1d4 < 1", vec![(0, ratio(1, 1))]),
+            (
+                "(1d6 >= 4) + (1d6 >= 4)",
+                vec![(0, ratio(1, 4)), (1, ratio(1, 2)), (2, ratio(1, 4))],
+            ),
+            ("-(1d6 > 3)", vec![(-1, ratio(1, 2)), (0, ratio(1, 2))]),
+        ] {
+            assert_eq!(outcomes(input), expected.into_iter().collect(), "{input}");
+        }
+    }
+
+    #[test]
+    fn order_statistics_match_brute_force() {
+        for count in 0..=5 {
+            for sides in [1, 2, 3, 4, 6] {
+                for start in 0..=count {
+                    for end in start..=count {
+                        let mut work = 0;
+                        let fast =
+                            Pmf::order_statistics(count, sides, start..end, &mut work).unwrap();
+                        let slow = brute_force(count, sides, start..end);
+
+                        assert_eq!(
+                            fast.weights, slow.weights,
+                            "{count}d{sides} keeping positions {start}..{end}"
+                        );
+                        assert_eq!(fast.total, slow.total);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn selections_match_enumerated_pools() {
+        let selections = [
+            (Selection::KeepHighest, 3),
+            (Selection::KeepLowest, 2),
+            (Selection::DropHighest, 1),
+            (Selection::DropLowest, 2),
+        ];
+
+        for (first, first_count) in selections {
+            for (second, second_count) in selections {
+                let select = |pool: Pool| {
+                    let pool = pool.select(first, first_count);
+                    let count = second_count.min(pool.len());
+                    pool.select(second, count)
+                };
+
+                let mut work = 0;
+                let window = select(Pool::roll(6, 4)).total(&mut work).unwrap();
+
+                let (sides, dice) = Pool::roll(6, 4).enumerate(&mut work).unwrap();
+                let enumerated = select(Pool::Dice { sides, dice }).total(&mut work).unwrap();
+
+                assert_eq!(
+                    window.weights, enumerated.weights,
+                    "6d4 {first:?} {first_count} then {second:?} {second_count}"
+                );
+            }
+        }
+    }
+}
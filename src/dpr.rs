@@ -0,0 +1,163 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module computes the damage dealt by an attack: an attack roll against
+//! a target's armor class followed by a damage roll, with the damage dice
+//! doubled on a critical hit.
+//!
+//! The natural result of the attack die decides crits and fumbles, so the
+//! attack is evaluated once for every natural result and the outcomes are
+//! weighted by the chance of rolling each one.
+
+use std::{collections::BTreeMap, error::Error};
+
+use num_rational::BigRational;
+use num_traits::Zero;
+
+use crate::ast::{Crit, CritRule, Node, Threshold};
+use crate::dist::{DistEvaluator, Distribution};
+use crate::eval::Arithmetic;
+use crate::limits::Limits;
+
+/// The outcome of an attack against a target.
+pub struct Attack {
+    /// The chance of a hit that is not critical.
+    pub hit: BigRational,
+
+    /// The chance of a critical hit.
+    pub crit: BigRational,
+
+    /// The chance of a miss.
+    pub miss: BigRational,
+
+    /// The distribution of damage dealt by one attack, including misses.
+    pub damage: Distribution,
+}
+
+/// Compute the outcome of rolling `attack` against armor class `ac` and then
+/// rolling `damage` on a hit. The crit range of the attack die (or `crit`, if
+/// given) decides critical hits, which double the damage dice.
+pub fn attack(
+    attack: &dyn Node,
+    ac: i32,
+    crit: Option<Threshold>,
+    damage: Box<dyn Node>,
+    arithmetic: Arithmetic,
+    limits: &Limits,
+) -> Result<Attack, Box<dyn Error>> {
+    let mut evaluator = DistEvaluator::new();
+    evaluator.arithmetic = arithmetic;
+    evaluator.limits = *limits;
+
+    let mut hit = BigRational::zero();
+    let mut crit_hit = BigRational::zero();
+    let mut miss = BigRational::zero();
+
+    for natural in 1..=20 {
+        evaluator.natural = Some(natural);
+        let totals = evaluator.eval(attack)?;
+        let Some(die) = evaluator.attack_die() else {
+            return Err("Attack must roll a single d20".into());
+        };
+
+        if die.fumble.contains(natural) {
+            miss += &die.chance;
+        } else if crit.unwrap_or(die.crit).contains(natural) {
+            crit_hit += &die.chance;
+        } else {
+            for (&total, p) in &totals.outcomes {
+                if total >= ac {
+                    hit += &die.chance * p;
+                } else {
+                    miss += &die.chance * p;
+                }
+            }
+        }
+    }
+
+    evaluator.natural = None;
+    let normal = evaluator.eval(damage.as_ref())?;
+
+    let critical = Crit::new(CritRule::Double, damage)?;
+    let critical = evaluator.eval(&critical)?;
+
+    // Mix the damage of each kind of hit according to its chance.
+    let mut outcomes = BTreeMap::new();
+    if !miss.is_zero() {
+        outcomes.insert(0, miss.clone());
+    }
+
+    for (chance, distribution) in [(&hit, &normal), (&crit_hit, &critical)] {
+        for (&total, p) in &distribution.outcomes {
+            *outcomes.entry(total).or_insert_with(BigRational::zero) += chance * p;
+        }
+    }
+
+    outcomes.retain(|_, p: &mut BigRational| !p.is_zero());
+
+    Ok(Attack {
+        hit,
+        crit: crit_hit,
+        miss,
+        damage: Distribution { outcomes },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::stats::Stats;
+
+    /// Roll `attack` against armor class `ac`, dealing `damage` on a hit.
+    fn roll_attack(attack: &str, ac: i32, damage: &str) -> Attack {
+        let limits = Limits::default();
+        let attack = parse(attack, &limits).unwrap();
+        let damage = parse(damage, &limits).unwrap();
+        super::attack(
+            attack.as_ref(),
+            ac,
+            None,
+            damage,
+            Arithmetic::Integer,
+            &limits,
+        )
+        .unwrap()
+    }
+
+    fn ratio(numer: i32, denom: i32) -> BigRational {
+        BigRational::new(numer.into(), denom.into())
+    }
+
+    #[test]
+    fn damage_per_round_has_known_values() {
+        // Natural rolls of 10 to 19 hit, a 20 crits, and anything else misses.
+        let attack = roll_attack("1d20 + 5", 15, "1d8 + 4");
+        assert_eq!(attack.hit, ratio(1, 2));
+        assert_eq!(attack.crit, ratio(1, 20));
+        assert_eq!(attack.miss, ratio(9, 20));
+
+        // A hit deals 8.5 on average and a crit, rolling 2d8 + 4, deals 13.
+        let stats = Stats::from(&attack.damage);
+        assert_eq!(stats.mean, ratio(49, 10));
+        assert_eq!(attack.damage.outcomes[&0], ratio(9, 20));
+        assert_eq!(attack.damage.outcomes[&20], ratio(1, 20 * 64));
+
+        // A natural 20 crits even when no total could hit.
+        let attack = roll_attack("1d20", 30, "1d8 + 4");
+        assert_eq!(attack.hit, ratio(0, 1));
+        assert_eq!(attack.crit, ratio(1, 20));
+    }
+
+    #[test]
+    fn selections_in_the_damage_are_evaluated() {
+        let plain = roll_attack("1d20 + 5", 15, "1d8 + 4");
+        let kept = roll_attack("1d20 + 5", 15, "1d8kh1 + 4");
+        assert_eq!(kept.damage.outcomes, plain.damage.outcomes);
+
+        // On a crit, 4d6kh3 is doubled to 8d6kh6.
+        let attack = roll_attack("1d20 + 5", 15, "4d6kh3");
+        assert_eq!(attack.damage.outcomes.keys().nth(1), Some(&3));
+        assert_eq!(attack.damage.outcomes.keys().next_back(), Some(&36));
+    }
+}
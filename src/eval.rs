@@ -5,16 +5,24 @@
 //! AST and returns the result of the expression.
 
 use std::{
+    cmp::Reverse,
     error::Error as StdError,
     fmt::{Display, Formatter, Result as FmtResult},
     ops::Range,
 };
 
+use num_rational::Rational64;
+use num_traits::{
+    checked_pow, CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Signed, ToPrimitive, Zero,
+};
 use rand::Rng;
 
 use crate::ast::{
-    Add, Div, Lit, Mul, Neg, Node, Roll, Select, Selection, Sub, Visitor, VisitorResult,
+    Add, Compare, Comparison, Crit, CritRule, Div, Func, Function, Lit, Mod, Mul, Neg, Node, Pow,
+    Roll, Select, Selection, Sub, Threshold, Visitor, VisitorResult,
 };
+use crate::limits::Limits;
+use crate::style::{bold, colored, Color};
 
 /// Possible ways to evaluate dice rolls.
 pub enum Evaluation<TRng: Rng> {
@@ -24,14 +32,37 @@ pub enum Evaluation<TRng: Rng> {
     /// Evaluate the expression as if all dice rolls were 1.
     Min,
 
-    /// Evaluate the expression as if all dice rolls landed in the middle of
-    /// their range (rounded down).
-    Mid,
-
     /// Evaluate the expression as if all dice rolls were the highest possible.
     Max,
 }
 
+/// Ways to round the exact result of an expression to an integer.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Rounding {
+    /// Round down, toward negative infinity.
+    Floor,
+
+    /// Round up, toward positive infinity.
+    Ceil,
+
+    /// Round to the nearest integer, with halves rounded away from zero.
+    Nearest,
+
+    /// Round to the nearest integer, with halves rounded up.
+    HalfUp,
+}
+
+/// Possible ways to perform arithmetic on intermediate results.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Arithmetic {
+    /// Round the result of every division (or negative exponent) down to an
+    /// integer before the next operation is performed.
+    Integer,
+
+    /// Keep intermediate results as exact fractions and round only the total.
+    Exact(Rounding),
+}
+
 /// A single die roll.
 pub struct DieRoll {
     /// The number of sides on the die. May be 4, 6, 8, 10, 12, 20, or 100.
@@ -42,8 +73,83 @@ pub struct DieRoll {
 
     /// Whether the roll was kept or discarded during a selection operation.
     pub keep: bool,
+
+    /// Whether the result falls within the critical success range of its roll.
+    pub crit: bool,
+
+    /// Whether the result falls within the critical failure range of its roll.
+    pub fumble: bool,
+}
+
+/// The result of evaluating a dice expression.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Outcome {
+    /// The total value of the expression.
+    pub total: i32,
+
+    /// The number of kept dice that rolled a critical success.
+    pub crits: usize,
+
+    /// The number of kept dice that rolled a critical failure.
+    pub fumbles: usize,
+}
+
+/// A single step of an evaluation, in the order in which it was taken.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Step {
+    /// Dice with the given number of sides were rolled.
+    Roll { sides: i32, results: Vec<i32> },
+
+    /// A selection kept some dice of a pool and dropped the others.
+    Select {
+        selection: Selection,
+        count: usize,
+        kept: Vec<i32>,
+        dropped: Vec<i32>,
+    },
+
+    /// A pool was rolled again and the roll with the higher or lower total
+    /// was kept.
+    Reroll {
+        selection: Selection,
+        first: i32,
+        second: i32,
+        kept: i32,
+    },
+
+    /// A maximized critical added the highest result of each kept die.
+    Maximize { sides: i32, count: usize },
+
+    /// The kept dice of a roll were added together.
+    Sum { kept: Vec<i32>, total: i64 },
+
+    /// A multiplied critical added together each repetition of an expression.
+    Repeat {
+        totals: Vec<Rational64>,
+        result: Rational64,
+    },
+
+    /// An operator was applied to one or two operands.
+    Operation {
+        operator: String,
+        operands: Vec<Rational64>,
+        result: Rational64,
+    },
+
+    /// A function was called.
+    Function {
+        function: Function,
+        args: Vec<Rational64>,
+        result: Rational64,
+    },
+
+    /// The result of the expression was rounded to an integer total.
+    Round { value: Rational64, total: i32 },
 }
 
+/// A hook that is called with each step of an evaluation.
+pub type Trace = Box<dyn FnMut(&Step)>;
+
 /// An implementation of the `Visitor` trait that evaluates each node in the AST
 /// using a stack and returns the result of the expression along with the
 /// individual die rolls.
@@ -51,18 +157,44 @@ pub struct Evaluator<TRng: Rng> {
     /// The rolls made during the evaluation.
     pub rolls: Vec<DieRoll>,
 
-    /// The evaluation strategy to use.
-    evaluation: Evaluation<TRng>,
+    /// The dice that make up each roll of the expression, in the order in
+    /// which the rolls were made. Each is a range over `rolls`, identified by
+    /// the address of the node that rolled it, since the same node may be
+    /// rolled more than once.
+    pub pools: Vec<(usize, Range<usize>)>,
+
+    /// How intermediate results are computed. Defaults to `Arithmetic::Integer`.
+    pub arithmetic: Arithmetic,
+
+    /// Limits on the work done during evaluation.
+    pub limits: Limits,
+
+    /// The number of repetitions made so far by `crit(..., mult<n>)`.
+    repetitions: usize,
+
+    /// The evaluation strategy to use. This may be replaced between calls to
+    /// `eval`, for example to reseed the random number generator.
+    pub evaluation: Evaluation<TRng>,
 
     /// A stack of intermediate results. Once the traversal is complete, the
     /// stack should contain a single value representing the result of the
     /// expression.
-    results: Vec<i32>,
+    results: Vec<Rational64>,
 
     /// A stack of dice pools that are being selected from. Pools are ranges
     /// over the `rolls` vector and are pushed when a new roll is made and
     /// then modified by selection operations.
     dice_pools: Vec<Range<usize>>,
+
+    /// Whether a maximized critical applies to rolls in the sub-expression
+    /// currently being evaluated. Other rules need no state: a doubled
+    /// critical is rewritten when it is parsed, and a multiplied one repeats
+    /// its whole expression.
+    maximize: bool,
+
+    /// If given, this is called with each step of the evaluation as it is
+    /// taken, to explain how the total came about.
+    pub trace: Option<Trace>,
 }
 
 /// Possible errors that can occur during evaluation.
@@ -80,6 +212,38 @@ pub enum Error {
 
     /// The stack was empty when an operation expected a value.
     StackUnderflow,
+
+    /// A result was too large to be represented.
+    Overflow,
+
+    /// A roll or selection was given a negative number of dice.
+    NegativeCount(i32),
+
+    /// Evaluating the expression would roll more dice than the limit allows.
+    TooManyDice(usize),
+
+    /// Evaluating the expression would repeat sub-expressions more times than
+    /// the limit allows.
+    TooManyRepetitions(usize),
+}
+
+/// The largest magnitude allowed for the numerator or denominator of an
+/// intermediate result. This keeps every product of two results within an
+/// `i64`, so that no operation on them can overflow before being checked.
+const MAX_MAGNITUDE: u64 = 1 << 31;
+
+/// Check that an arithmetic operation did not overflow and that its result is
+/// within `MAX_MAGNITUDE`.
+fn checked(value: Option<Rational64>) -> Result<Rational64, Error> {
+    match value {
+        Some(value)
+            if value.numer().unsigned_abs() <= MAX_MAGNITUDE
+                && value.denom().unsigned_abs() <= MAX_MAGNITUDE =>
+        {
+            Ok(value)
+        }
+        _ => Err(Error::Overflow),
+    }
 }
 
 impl<TRng: Rng> Evaluator<TRng> {
@@ -87,41 +251,200 @@ impl<TRng: Rng> Evaluator<TRng> {
         Self {
             evaluation,
             rolls: Vec::new(),
+            pools: Vec::new(),
+            arithmetic: Arithmetic::Integer,
+            limits: Limits::default(),
+            repetitions: 0,
             results: Vec::new(),
             dice_pools: Vec::new(),
+            maximize: false,
+            trace: None,
         }
     }
 
-    pub fn eval(&mut self, node: &dyn Node) -> Result<i32, Box<dyn std::error::Error>> {
+    pub fn eval(&mut self, node: &dyn Node) -> Result<Outcome, Box<dyn std::error::Error>> {
         self.rolls.clear();
+        self.pools.clear();
+        self.results.clear();
+        self.dice_pools.clear();
+        self.maximize = false;
+        self.repetitions = 0;
         node.accept(self)?;
 
-        self.results.pop().ok_or(Box::new(Error::StackUnderflow))
+        let value = self.results.pop().ok_or(Box::new(Error::StackUnderflow))?;
+        let total = self.arithmetic.total(value)?;
+        if let Some(trace) = &mut self.trace {
+            if !value.is_integer() {
+                trace(&Step::Round { value, total });
+            }
+        }
+        let kept = self.rolls.iter().filter(|r| r.keep);
+
+        Ok(Outcome {
+            total,
+            crits: kept.clone().filter(|r| r.crit).count(),
+            fumbles: kept.filter(|r| r.fumble).count(),
+        })
+    }
+
+    /// Pop an intermediate result that is used as a count, such as the number
+    /// of dice to roll, rounding it down to an integer.
+    fn pop_count(&mut self) -> Result<i32, Box<dyn StdError>> {
+        let Some(count) = self.results.pop() else {
+            return Err(Box::new(Error::StackUnderflow));
+        };
+
+        let count = count.floor().to_integer().to_i32().ok_or(Error::Overflow)?;
+        if count < 0 {
+            return Err(Box::new(Error::NegativeCount(count)));
+        }
+
+        Ok(count)
+    }
+
+    /// Push the result of an operation, tracing it first.
+    fn reduce(&mut self, operator: impl Display, operands: &[Rational64], result: Rational64) {
+        if let Some(trace) = &mut self.trace {
+            trace(&Step::Operation {
+                operator: operator.to_string(),
+                operands: operands.to_vec(),
+                result,
+            });
+        }
+
+        self.results.push(result);
+    }
+
+    /// Check that rolling `count` more dice stays within the limit.
+    fn reserve_dice(&self, count: usize) -> Result<(), Error> {
+        if self.rolls.len().saturating_add(count) > self.limits.max_dice {
+            return Err(Error::TooManyDice(self.limits.max_dice));
+        }
+
+        Ok(())
+    }
+}
+
+impl Arithmetic {
+    /// Round a quotient down to an integer when using integer arithmetic.
+    fn quotient(self, value: Rational64) -> Rational64 {
+        match self {
+            Arithmetic::Integer => value.floor(),
+            Arithmetic::Exact(_) => value,
+        }
+    }
+
+    /// Round the result of an expression to an integer total.
+    pub fn total(self, value: Rational64) -> Result<i32, Error> {
+        let value = match self {
+            Arithmetic::Integer => value.floor(),
+            Arithmetic::Exact(rounding) => rounding.round(value),
+        };
+
+        value.to_integer().to_i32().ok_or(Error::Overflow)
+    }
+
+    pub fn neg(self, right: Rational64) -> Result<Rational64, Error> {
+        checked(Rational64::zero().checked_sub(&right))
+    }
+
+    pub fn add(self, left: Rational64, right: Rational64) -> Result<Rational64, Error> {
+        checked(left.checked_add(&right))
+    }
+
+    pub fn sub(self, left: Rational64, right: Rational64) -> Result<Rational64, Error> {
+        checked(left.checked_sub(&right))
+    }
+
+    pub fn mul(self, left: Rational64, right: Rational64) -> Result<Rational64, Error> {
+        checked(left.checked_mul(&right))
+    }
+
+    pub fn div(self, left: Rational64, right: Rational64) -> Result<Rational64, Error> {
+        if right.is_zero() {
+            return Err(Error::DivideByZero);
+        }
+
+        Ok(self.quotient(checked(left.checked_div(&right))?))
+    }
+
+    pub fn modulo(self, left: Rational64, right: Rational64) -> Result<Rational64, Error> {
+        if right.is_zero() {
+            return Err(Error::DivideByZero);
+        }
+
+        // Take the sign of the divisor to agree with division rounding down.
+        let quotient = checked(left.checked_div(&right))?.floor();
+        let product = checked(right.checked_mul(&quotient))?;
+        checked(left.checked_sub(&product))
+    }
+
+    pub fn pow(self, left: Rational64, right: Rational64) -> Result<Rational64, Error> {
+        // Fractional exponents are not supported, so the exponent is rounded
+        // down to an integer.
+        let Some(right) = right.floor().to_integer().to_i32() else {
+            return Err(Error::Overflow);
+        };
+
+        if right < 0 && left.is_zero() {
+            return Err(Error::DivideByZero);
+        }
+
+        let base = if right < 0 { left.recip() } else { left };
+        let result = checked(checked_pow(base, right.unsigned_abs() as usize))?;
+        Ok(if right < 0 {
+            self.quotient(result)
+        } else {
+            result
+        })
+    }
+
+    pub fn func(self, function: Function, args: &[Rational64]) -> Result<Rational64, Error> {
+        let result = match function {
+            Function::Floor => args.first().map(|arg| arg.floor()),
+            Function::Ceil => args.first().map(|arg| arg.ceil()),
+            Function::Round => args.first().map(|arg| arg.round()),
+            Function::Abs => args.first().map(|arg| arg.abs()),
+            Function::Min => args.iter().min().copied(),
+            Function::Max => args.iter().max().copied(),
+        };
+
+        result.ok_or(Error::StackUnderflow)
+    }
+}
+
+impl Rounding {
+    /// Round `value` to an integer according to this rounding mode.
+    pub fn round(self, value: Rational64) -> Rational64 {
+        match self {
+            Rounding::Floor => value.floor(),
+            Rounding::Ceil => value.ceil(),
+            Rounding::Nearest => value.round(),
+            Rounding::HalfUp => (value + Rational64::new(1, 2)).floor(),
+        }
     }
 }
 
 impl<TRng: Rng> Visitor for Evaluator<TRng> {
     fn lit(&mut self, node: &Lit) -> VisitorResult {
-        self.results.push(node.value);
+        self.results
+            .push(Rational64::from_integer(node.value.into()));
         Ok(())
     }
 
     fn roll(&mut self, node: &Roll) -> VisitorResult {
         node.count.accept(self)?;
-        let Some(count) = self.results.pop() else {
-            return Err(Box::new(Error::StackUnderflow));
-        };
+        let count = self.pop_count()?;
 
         node.sides.accept(self)?;
-        let Some(sides) = self.results.pop() else {
-            return Err(Box::new(Error::StackUnderflow));
-        };
+        let sides = self.pop_count()?;
+
+        self.reserve_dice(count as usize)?;
 
         for _ in 0..count {
             let roll = match &mut self.evaluation {
                 Evaluation::Rand(rng) => rng.gen_range(1..sides + 1),
                 Evaluation::Min => 1,
-                Evaluation::Mid => sides / 2,
                 Evaluation::Max => sides,
             };
 
@@ -129,25 +452,88 @@ impl<TRng: Rng> Visitor for Evaluator<TRng> {
                 sides,
                 result: roll,
                 keep: true,
+                crit: false,
+                fumble: false,
             });
         }
 
         let pool = self.rolls.len() - count as usize..self.rolls.len();
+        if let Some(trace) = &mut self.trace {
+            trace(&Step::Roll {
+                sides,
+                results: results(&self.rolls[pool.clone()]),
+            });
+        }
+
         if let Some(select) = &node.select {
             self.dice_pools.push(pool.clone());
             select.accept(self)?;
             self.dice_pools.pop();
         }
 
-        self.rolls[pool.start..pool.end].sort_unstable_by(|a, b| b.result.cmp(&a.result));
+        self.rolls[pool.start..pool.end].sort_unstable_by_key(|r| Reverse(r.result));
 
-        let total = self
-            .rolls
+        // Natural 20s and 1s are critical on a d20 unless other ranges are
+        // given. Flag every die from this roll, including any rerolled by a
+        // selection.
+        let natural = sides == 20;
+        let crit = node.crit.or(natural.then_some(Threshold {
+            comparison: Comparison::Equal,
+            value: sides,
+        }));
+        let fumble = node.fumble.or(natural.then_some(Threshold {
+            comparison: Comparison::Equal,
+            value: 1,
+        }));
+
+        for roll in &mut self.rolls[pool.start..] {
+            roll.crit = crit.is_some_and(|t| t.contains(roll.result));
+            roll.fumble = fumble.is_some_and(|t| t.contains(roll.result));
+        }
+
+        // A maximized critical adds the highest result of each kept die on top
+        // of the normal roll.
+        if self.maximize {
+            let kept = self.rolls[pool.start..].iter().filter(|r| r.keep).count();
+            self.reserve_dice(kept)?;
+            if let Some(trace) = &mut self.trace {
+                trace(&Step::Maximize { sides, count: kept });
+            }
+
+            for _ in 0..kept {
+                self.rolls.push(DieRoll {
+                    sides,
+                    result: sides,
+                    keep: true,
+                    crit: false,
+                    fumble: false,
+                });
+            }
+        }
+
+        let total: i64 = self.rolls[pool.start..]
             .iter()
-            .map(|r| if r.keep { r.result } else { 0 })
+            .map(|r| if r.keep { r.result.into() } else { 0 })
             .sum();
 
-        self.results.push(total);
+        self.pools
+            .push((node as *const Roll as usize, pool.start..self.rolls.len()));
+
+        if let Some(trace) = &mut self.trace {
+            let kept: Vec<i32> = self.rolls[pool.start..]
+                .iter()
+                .filter(|r| r.keep)
+                .map(|r| r.result)
+                .collect();
+
+            // The total of a single die is evident from the roll itself.
+            if kept.len() > 1 {
+                trace(&Step::Sum { kept, total });
+            }
+        }
+
+        self.results
+            .push(checked(Some(Rational64::from_integer(total)))?);
 
         Ok(())
     }
@@ -172,7 +558,7 @@ impl<TRng: Rng> Visitor for Evaluator<TRng> {
                 let count = match &node.count {
                     Some(child) => {
                         child.accept(self)?;
-                        self.results.pop().ok_or(Box::new(Error::StackUnderflow))? as usize
+                        self.pop_count()? as usize
                     }
                     None => 1,
                 };
@@ -185,11 +571,9 @@ impl<TRng: Rng> Visitor for Evaluator<TRng> {
                 }
 
                 if high {
-                    self.rolls[pool.start..pool.end]
-                        .sort_unstable_by(|a, b| b.result.cmp(&a.result));
+                    self.rolls[pool.start..pool.end].sort_unstable_by_key(|r| Reverse(r.result));
                 } else {
-                    self.rolls[pool.start..pool.end]
-                        .sort_unstable_by(|a, b| a.result.cmp(&b.result));
+                    self.rolls[pool.start..pool.end].sort_unstable_by_key(|r| r.result);
                 }
 
                 for i in 0..count {
@@ -200,6 +584,22 @@ impl<TRng: Rng> Visitor for Evaluator<TRng> {
                     self.rolls[pool.start + i].keep = !keep;
                 }
 
+                if let Some(trace) = &mut self.trace {
+                    let (chosen, rest) = self.rolls[pool.clone()].split_at(count);
+                    let (kept, dropped) = if keep {
+                        (results(chosen), results(rest))
+                    } else {
+                        (results(rest), results(chosen))
+                    };
+
+                    trace(&Step::Select {
+                        selection: node.selection,
+                        count,
+                        kept,
+                        dropped,
+                    });
+                }
+
                 if let Some(next) = &node.next {
                     let remaining = if keep {
                         pool.start..pool.start + count
@@ -217,12 +617,12 @@ impl<TRng: Rng> Visitor for Evaluator<TRng> {
 
             Selection::Advantage | Selection::Disadvantage => {
                 // Reroll the current pool and select the highest/lowest total of the two rolls
+                self.reserve_dice(pool.len())?;
                 for i in pool.clone() {
                     let sides = self.rolls[i].sides;
                     let roll = match &mut self.evaluation {
                         Evaluation::Rand(rng) => rng.gen_range(1..sides + 1),
                         Evaluation::Min => 1,
-                        Evaluation::Mid => sides / 2,
                         Evaluation::Max => sides,
                     };
 
@@ -230,6 +630,8 @@ impl<TRng: Rng> Visitor for Evaluator<TRng> {
                         sides,
                         result: roll,
                         keep: true,
+                        crit: false,
+                        fumble: false,
                     });
                 }
 
@@ -238,7 +640,24 @@ impl<TRng: Rng> Visitor for Evaluator<TRng> {
 
                 let total_old: i32 = self.rolls[old.clone()].iter().map(|r| r.result).sum();
                 let total_new: i32 = self.rolls[new.clone()].iter().map(|r| r.result).sum();
-                let kept = if (total_new > total_old) == (node.selection == Selection::Advantage) {
+                let keep_new = (total_new > total_old) == (node.selection == Selection::Advantage);
+                if let Some(trace) = &mut self.trace {
+                    // An empty pool rerolls nothing, so there is no roll to show.
+                    if let Some(die) = self.rolls[new.clone()].first() {
+                        trace(&Step::Roll {
+                            sides: die.sides,
+                            results: results(&self.rolls[new.clone()]),
+                        });
+                    }
+                    trace(&Step::Reroll {
+                        selection: node.selection,
+                        first: total_old,
+                        second: total_new,
+                        kept: if keep_new { total_new } else { total_old },
+                    });
+                }
+
+                let kept = if keep_new {
                     for roll in old {
                         self.rolls[roll].keep = false
                     }
@@ -261,12 +680,55 @@ impl<TRng: Rng> Visitor for Evaluator<TRng> {
         }
     }
 
+    fn crit(&mut self, node: &Crit) -> VisitorResult {
+        let outer = std::mem::replace(&mut self.maximize, node.rule == CritRule::Maximize);
+
+        let mut totals = Vec::new();
+        let result = match node.rule {
+            CritRule::Multiply(times) => (0..times).try_fold(Rational64::zero(), |total, _| {
+                self.repetitions += 1;
+                if self.repetitions > self.limits.max_repetitions {
+                    return Err(
+                        Box::new(Error::TooManyRepetitions(self.limits.max_repetitions))
+                            as Box<dyn StdError>,
+                    );
+                }
+
+                node.expr.accept(self)?;
+                match self.results.pop() {
+                    Some(result) => {
+                        totals.push(result);
+                        Ok(self.arithmetic.add(total, result)?)
+                    }
+                    None => Err(Box::new(Error::StackUnderflow) as Box<dyn StdError>),
+                }
+            }),
+            CritRule::Double | CritRule::Maximize => node.operand().accept(self).and_then(|_| {
+                self.results
+                    .pop()
+                    .ok_or(Box::new(Error::StackUnderflow) as Box<dyn StdError>)
+            }),
+        };
+
+        self.maximize = outer;
+        let result = result?;
+        if let Some(trace) = &mut self.trace {
+            if totals.len() > 1 {
+                trace(&Step::Repeat { totals, result });
+            }
+        }
+
+        self.results.push(result);
+        Ok(())
+    }
+
     fn neg(&mut self, node: &Neg) -> VisitorResult {
         node.right.accept(self)?;
         let Some(right) = self.results.pop() else {
             return Err(Box::new(Error::StackUnderflow));
         };
-        self.results.push(-right);
+        let result = self.arithmetic.neg(right)?;
+        self.reduce("-", &[right], result);
         Ok(())
     }
 
@@ -280,7 +742,8 @@ impl<TRng: Rng> Visitor for Evaluator<TRng> {
             return Err(Box::new(Error::StackUnderflow));
         };
 
-        self.results.push(left + right);
+        let result = self.arithmetic.add(left, right)?;
+        self.reduce("+", &[left, right], result);
         Ok(())
     }
 
@@ -294,7 +757,8 @@ impl<TRng: Rng> Visitor for Evaluator<TRng> {
             return Err(Box::new(Error::StackUnderflow));
         };
 
-        self.results.push(left - right);
+        let result = self.arithmetic.sub(left, right)?;
+        self.reduce("-", &[left, right], result);
         Ok(())
     }
 
@@ -308,7 +772,8 @@ impl<TRng: Rng> Visitor for Evaluator<TRng> {
             return Err(Box::new(Error::StackUnderflow));
         };
 
-        self.results.push(left * right);
+        let result = self.arithmetic.mul(left, right)?;
+        self.reduce("×", &[left, right], result);
         Ok(())
     }
 
@@ -322,29 +787,214 @@ impl<TRng: Rng> Visitor for Evaluator<TRng> {
             return Err(Box::new(Error::StackUnderflow));
         };
 
-        if right == 0 {
-            return Err(Box::new(Error::DivideByZero));
+        let result = self.arithmetic.div(left, right)?;
+        self.reduce("/", &[left, right], result);
+        Ok(())
+    }
+
+    fn modulo(&mut self, node: &Mod) -> VisitorResult {
+        node.left.accept(self)?;
+        let Some(left) = self.results.pop() else {
+            return Err(Box::new(Error::StackUnderflow));
+        };
+        node.right.accept(self)?;
+        let Some(right) = self.results.pop() else {
+            return Err(Box::new(Error::StackUnderflow));
+        };
+
+        let result = self.arithmetic.modulo(left, right)?;
+        self.reduce("%", &[left, right], result);
+        Ok(())
+    }
+
+    fn pow(&mut self, node: &Pow) -> VisitorResult {
+        node.left.accept(self)?;
+        let Some(left) = self.results.pop() else {
+            return Err(Box::new(Error::StackUnderflow));
+        };
+        node.right.accept(self)?;
+        let Some(right) = self.results.pop() else {
+            return Err(Box::new(Error::StackUnderflow));
+        };
+
+        let result = self.arithmetic.pow(left, right)?;
+        self.reduce("^", &[left, right], result);
+        Ok(())
+    }
+
+    fn func(&mut self, node: &Func) -> VisitorResult {
+        let mut args = Vec::with_capacity(node.args.len());
+        for arg in &node.args {
+            arg.accept(self)?;
+            let Some(arg) = self.results.pop() else {
+                return Err(Box::new(Error::StackUnderflow));
+            };
+            args.push(arg);
         }
 
-        self.results.push(left / right);
+        let result = self.arithmetic.func(node.function, &args)?;
+        if let Some(trace) = &mut self.trace {
+            trace(&Step::Function {
+                function: node.function,
+                args,
+                result,
+            });
+        }
+
+        self.results.push(result);
+        Ok(())
+    }
+
+    fn compare(&mut self, node: &Compare) -> VisitorResult {
+        node.left.accept(self)?;
+        let Some(left) = self.results.pop() else {
+            return Err(Box::new(Error::StackUnderflow));
+        };
+        node.right.accept(self)?;
+        let Some(right) = self.results.pop() else {
+            return Err(Box::new(Error::StackUnderflow));
+        };
+
+        let result = node.comparison.test(left, right);
+        self.reduce(
+            node.comparison,
+            &[left, right],
+            Rational64::from_integer(result.into()),
+        );
         Ok(())
     }
 }
 
+/// Dropped dice are surrounded by `~~`, and kept dice that crit or fumble are
+/// followed by `!` or `?`, so that they can be told apart without color.
 impl Display for DieRoll {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-        if self.keep {
-            write!(
-                f,
-                "\x1B[32m[d{}:\x1B[22m\x1B[1m{}\x1B[22m]\x1B[39m",
-                self.sides, self.result
-            )
+        let roll = format!("[d{}:{}]", self.sides, bold(self.result));
+        if self.keep && self.crit {
+            write!(f, "{}", colored(Color::Yellow, format!("{roll}!")))
+        } else if self.keep && self.fumble {
+            write!(f, "{}", colored(Color::Magenta, format!("{roll}?")))
+        } else if self.keep {
+            write!(f, "{}", colored(Color::Green, roll))
         } else {
-            write!(
-                f,
-                "\x1B[9m\x1B[31m[d{}:\x1B[22m\x1B[1m{}\x1B[22m]\x1B[39m\x1B[29m",
-                self.sides, self.result
-            )
+            write!(f, "{}", colored(Color::Red, format!("~~{roll}~~")))
+        }
+    }
+}
+
+/// The results of some dice.
+fn results(rolls: &[DieRoll]) -> Vec<i32> {
+    rolls.iter().map(|r| r.result).collect()
+}
+
+/// Write a list of values separated by `separator`, or "none" if it is empty.
+fn write_list<T: Display>(f: &mut Formatter<'_>, values: &[T], separator: &str) -> FmtResult {
+    if values.is_empty() {
+        return write!(f, "none");
+    }
+
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{separator}")?;
+        }
+
+        write!(f, "{value}")?;
+    }
+
+    Ok(())
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Step::Roll { sides, results } => {
+                write!(f, "rolled {}d{sides}: ", results.len())?;
+                write_list(f, results, ", ")
+            }
+
+            Step::Select {
+                selection,
+                count,
+                kept,
+                dropped,
+            } => {
+                let side = match selection {
+                    Selection::KeepHighest | Selection::DropHighest => "highest",
+                    _ => "lowest",
+                };
+
+                write!(f, "{}{count}: ", selection.name())?;
+                if let Selection::KeepHighest | Selection::KeepLowest = selection {
+                    write!(f, "kept the {side} {count}: ")?;
+                    write_list(f, kept, ", ")?;
+                    write!(f, "; dropped ")?;
+                    write_list(f, dropped, ", ")
+                } else {
+                    write!(f, "dropped the {side} {count}: ")?;
+                    write_list(f, dropped, ", ")?;
+                    write!(f, "; kept ")?;
+                    write_list(f, kept, ", ")
+                }
+            }
+
+            Step::Reroll {
+                selection,
+                first,
+                second,
+                kept,
+            } => {
+                let side = match selection {
+                    Selection::Advantage => "higher",
+                    _ => "lower",
+                };
+
+                write!(
+                    f,
+                    "{}: {first} vs {second} on the reroll, kept the {side} total {kept}",
+                    selection.name()
+                )
+            }
+
+            Step::Maximize { sides, count } => {
+                let bonus = i64::from(*sides) * *count as i64;
+                write!(f, "crit max: added {count} × {sides} = {bonus}")
+            }
+
+            Step::Sum { kept, total } => {
+                write!(f, "added the kept dice: ")?;
+                write_list(f, kept, " + ")?;
+                write!(f, " = {total}")
+            }
+
+            Step::Repeat { totals, result } => {
+                write!(f, "crit mult{}: ", totals.len())?;
+                write_list(f, totals, " + ")?;
+                write!(f, " = {result}")
+            }
+
+            Step::Operation {
+                operator,
+                operands,
+                result,
+            } => match operands.as_slice() {
+                [right] => write!(f, "{operator}({right}) = {result}"),
+                operands => {
+                    write_list(f, operands, &format!(" {operator} "))?;
+                    write!(f, " = {result}")
+                }
+            },
+
+            Step::Function {
+                function,
+                args,
+                result,
+            } => {
+                write!(f, "{}(", function.name())?;
+                write_list(f, args, ", ")?;
+                write!(f, ") = {result}")
+            }
+
+            Step::Round { value, total } => write!(f, "rounded {value} to {total}"),
         }
     }
 }
@@ -364,6 +1014,290 @@ impl Display for Error {
             ),
             Error::DivideByZero => write!(f, "Division by zero"),
             Error::StackUnderflow => write!(f, "Stack underflow"),
+            Error::Overflow => write!(f, "Arithmetic overflow"),
+            Error::NegativeCount(count) => write!(f, "Cannot roll or select {} dice", count),
+            Error::TooManyDice(limit) => write!(f, "Cannot roll more than {} dice", limit),
+            Error::TooManyRepetitions(limit) => {
+                write!(f, "Cannot repeat sub-expressions more than {} times", limit)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+    use crate::parser::parse;
+
+    /// Evaluate `input` with every die rolling its maximum.
+    fn eval(input: &str) -> Result<Outcome, Box<dyn StdError>> {
+        let limits = Limits::default();
+        let root = parse(input, &limits)?;
+        let mut evaluator = Evaluator::<StepRng>::new(Evaluation::Max);
+        evaluator.eval(root.as_ref())
+    }
+
+    /// Whether evaluating `input` fails with `expected`.
+    fn fails_with(input: &str, expected: &Error) -> bool {
+        match eval(input) {
+            Err(err) => err.to_string() == expected.to_string(),
+            Ok(_) => false,
+        }
+    }
+
+    /// Evaluate `input` with every die rolling its maximum or minimum, and
+    /// return the outcome along with the flags of each die rolled.
+    fn flags(input: &str, evaluation: Evaluation<StepRng>) -> (Outcome, Vec<(bool, bool, bool)>) {
+        let root = parse(input, &Limits::default()).unwrap();
+        let mut evaluator = Evaluator::new(evaluation);
+        let outcome = evaluator.eval(root.as_ref()).unwrap();
+        let flags = evaluator
+            .rolls
+            .iter()
+            .map(|roll| (roll.keep, roll.crit, roll.fumble))
+            .collect();
+        (outcome, flags)
+    }
+
+    #[test]
+    fn natural_results_of_a_d20_crit_and_fumble() {
+        let (outcome, rolls) = flags("1d20", Evaluation::Max);
+        assert_eq!((outcome.crits, outcome.fumbles), (1, 0));
+        assert_eq!(rolls, [(true, true, false)]);
+
+        let (outcome, rolls) = flags("1d20 + 5", Evaluation::Min);
+        assert_eq!((outcome.crits, outcome.fumbles), (0, 1));
+        assert_eq!(rolls, [(true, false, true)]);
+
+        // Other dice never crit unless given a range.
+        let (outcome, rolls) = flags("1d6 + 1d6cf=6", Evaluation::Max);
+        assert_eq!((outcome.crits, outcome.fumbles), (0, 1));
+        assert_eq!(rolls, [(true, false, false), (true, false, true)]);
+
+        let (outcome, _) = flags("2d6cs>=6 + 1d20cs>=19cf<=2", Evaluation::Min);
+        assert_eq!((outcome.crits, outcome.fumbles), (0, 1));
+        let (outcome, _) = flags("2d6cs>=6 + 1d20cs>=19cf<=2", Evaluation::Max);
+        assert_eq!((outcome.crits, outcome.fumbles), (3, 0));
+    }
+
+    #[test]
+    fn dropped_dice_are_flagged_but_not_counted() {
+        let (outcome, rolls) = flags("2d20kh1", Evaluation::Max);
+        assert_eq!((outcome.crits, outcome.fumbles), (1, 0));
+        assert_eq!(rolls, [(true, true, false), (false, true, false)]);
+
+        let (outcome, rolls) = flags("3d20dl2", Evaluation::Min);
+        assert_eq!((outcome.crits, outcome.fumbles), (0, 1));
+        assert!(rolls.iter().all(|&(_, _, fumble)| fumble));
+        assert_eq!(rolls.iter().filter(|&&(keep, _, _)| keep).count(), 1);
+    }
+
+    #[test]
+    fn rerolled_dice_are_flagged() {
+        // With equal totals, advantage keeps the first roll and disadvantage
+        // keeps the reroll.
+        let (outcome, rolls) = flags("1d20adv", Evaluation::Max);
+        assert_eq!((outcome.crits, outcome.fumbles), (1, 0));
+        assert_eq!(rolls, [(true, true, false), (false, true, false)]);
+
+        let (outcome, rolls) = flags("1d20dis", Evaluation::Min);
+        assert_eq!((outcome.crits, outcome.fumbles), (0, 1));
+        assert_eq!(rolls, [(false, false, true), (true, false, true)]);
+
+        // Only the die kept by the first selection is rerolled.
+        let (outcome, rolls) = flags("2d20kh1adv", Evaluation::Max);
+        assert_eq!((outcome.crits, outcome.fumbles), (1, 0));
+        assert_eq!(rolls.len(), 3);
+        assert!(rolls.iter().all(|&(_, crit, _)| crit));
+    }
+
+    #[test]
+    fn remainders_take_the_sign_of_the_divisor() {
+        for (input, expected) in [
+            ("7 % 3", 1),
+            ("-7 % 3", 2),
+            ("7 % -3", -2),
+            ("-7 % -3", -1),
+            ("-6 % 3", 0),
+            ("-7 / 2", -4),
+        ] {
+            assert_eq!(eval(input).unwrap().total, expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn powers_bind_tighter_than_negation_and_associate_right() {
+        for (input, expected) in [
+            ("2^3^2", 512),
+            ("(2^3)^2", 64),
+            ("-2^2", -4),
+            ("(-2)^2", 4),
+            ("-2^3", -8),
+            ("2 * 3^2", 18),
+            ("2^-1", 0),
+            ("1d4^2", 16),
+        ] {
+            assert_eq!(eval(input).unwrap().total, expected, "{input}");
+        }
+    }
+
+    /// Evaluate `input` with `arithmetic` and every die rolling its maximum.
+    fn total_with(input: &str, arithmetic: Arithmetic) -> i32 {
+        let root = parse(input, &Limits::default()).unwrap();
+        let mut evaluator = Evaluator::<StepRng>::new(Evaluation::Max);
+        evaluator.arithmetic = arithmetic;
+        evaluator.eval(root.as_ref()).unwrap().total
+    }
+
+    #[test]
+    fn negative_halves_are_rounded_by_each_mode() {
+        let modes = [
+            Arithmetic::Integer,
+            Arithmetic::Exact(Rounding::Floor),
+            Arithmetic::Exact(Rounding::Ceil),
+            Arithmetic::Exact(Rounding::Nearest),
+            Arithmetic::Exact(Rounding::HalfUp),
+        ];
+
+        for (input, expected) in [
+            ("-5 / 2", [-3, -3, -2, -3, -2]),
+            ("-1 / 2", [-1, -1, 0, -1, 0]),
+            ("5 / 2", [2, 2, 3, 3, 3]),
+            ("-7 / 4", [-2, -2, -1, -2, -2]),
+            ("-1d6 / 4", [-2, -2, -1, -2, -1]),
+            // Integer arithmetic rounds each division before the next one.
+            ("-1 / 2 * 3", [-3, -2, -1, -2, -1]),
+        ] {
+            for (arithmetic, expected) in modes.into_iter().zip(expected) {
+                assert_eq!(
+                    total_with(input, arithmetic),
+                    expected,
+                    "{input} with {arithmetic:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rounding_functions_round_negative_halves() {
+        let exact = Arithmetic::Exact(Rounding::Floor);
+        for (input, expected) in [
+            ("floor(-5 / 2)", -3),
+            ("ceil(-5 / 2)", -2),
+            ("round(-5 / 2)", -3),
+            ("round(-1 / 2)", -1),
+            ("round(5 / 2)", 3),
+            ("round(-7 / 4)", -2),
+            ("ceil(-5 / 2) * 2", -4),
+            ("round(-5 / 2) * 2", -6),
+        ] {
+            assert_eq!(total_with(input, exact), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        for input in [
+            "2^100",
+            "(-2147483647-1)/-1",
+            "2147483647 + 1",
+            "-2147483647 - 2",
+            "65536 * 65536",
+            "(-2)^33",
+            "crit(2147483647, mult2)",
+        ] {
+            assert!(
+                fails_with(input, &Error::Overflow),
+                "{input} should overflow"
+            );
+        }
+
+        assert_eq!(eval("2147483647").unwrap().total, i32::MAX);
+        assert_eq!(eval("-2147483647 - 1").unwrap().total, i32::MIN);
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        for input in ["1/0", "1d6 % 0", "0^-1", "(1d6 - 6)^-2"] {
+            assert!(fails_with(input, &Error::DivideByZero), "{input}");
+        }
+    }
+
+    #[test]
+    fn dice_beyond_limit_are_an_error() {
+        let limits = Limits::default();
+        let expected = Error::TooManyDice(limits.max_dice);
+        for input in [
+            "10000d6*10000d6",
+            "10001d6",
+            "5001d6 adv",
+            "crit(5001d6)",
+            "crit(5001d6, max)",
+        ] {
+            assert!(fails_with(input, &expected), "{input}");
         }
+
+        assert!(eval("10000d6").is_ok());
+    }
+
+    #[test]
+    fn repetitions_beyond_limit_are_an_error() {
+        let limits = Limits::default();
+        let expected = Error::TooManyRepetitions(limits.max_repetitions);
+        assert!(fails_with("crit(crit(1, mult40), mult40)", &expected));
+    }
+
+    /// Evaluate `input` with every die rolling its maximum, and return each
+    /// step of the evaluation.
+    fn explain(input: &str) -> Result<Vec<Step>, Box<dyn StdError>> {
+        let root = parse(input, &Limits::default())?;
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        let mut evaluator = Evaluator::<StepRng>::new(Evaluation::Max);
+        let trace = Rc::clone(&steps);
+        evaluator.trace = Some(Box::new(move |step: &Step| {
+            trace.borrow_mut().push(step.clone())
+        }));
+
+        evaluator.eval(root.as_ref())?;
+        drop(evaluator);
+        Ok(Rc::try_unwrap(steps).unwrap().into_inner())
+    }
+
+    #[test]
+    fn empty_pools_can_be_explained() {
+        for input in [
+            "0d6adv",
+            "0d6dis",
+            "0d6kh0",
+            "0d6dl0",
+            "0d6kh0adv",
+            "2d6kh0adv",
+            "2d6dl2dis",
+            "1d6kl0kh0",
+        ] {
+            let steps = explain(input).unwrap_or_else(|err| panic!("{input}: {err}"));
+            assert!(!steps.is_empty(), "{input}");
+        }
+
+        // Rerolling an empty pool rolls nothing, so only the reroll is shown.
+        let steps = explain("2d6kh0adv").unwrap();
+        assert!(!steps[2..]
+            .iter()
+            .any(|step| matches!(step, Step::Roll { .. })));
+        assert!(matches!(
+            steps[2],
+            Step::Reroll {
+                first: 0,
+                second: 0,
+                kept: 0,
+                ..
+            }
+        ));
+
+        assert!(explain("0d6kh1").is_err());
     }
 }
@@ -0,0 +1,143 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module writes distributions and statistics in machine-readable formats
+//! for use in spreadsheets and notebooks. Every probability or statistic is
+//! given both as an exact fraction (as a string, since it may not fit in any
+//! numeric type) and as the nearest floating-point number.
+
+use std::io::{Result, Write};
+
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+
+use crate::dist::Distribution;
+use crate::stats::Stats;
+
+/// Machine-readable output formats.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    /// Comma-separated values with a header row.
+    Csv,
+
+    /// A JSON document.
+    Json,
+}
+
+/// Write the probability of each total of `distribution` along with the
+/// chance of rolling at least and at most that total.
+pub fn write_distribution<W: Write>(
+    writer: &mut W,
+    format: Format,
+    distribution: &Distribution,
+) -> Result<()> {
+    let rows = distribution.cumulative();
+
+    match format {
+        Format::Csv => {
+            writeln!(
+                writer,
+                "outcome,probability,probability_exact,at_least,at_least_exact,at_most,at_most_exact"
+            )?;
+
+            for row in rows {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{}",
+                    row.outcome,
+                    float(row.probability),
+                    row.probability,
+                    float(&row.at_least),
+                    row.at_least,
+                    float(&row.at_most),
+                    row.at_most
+                )?;
+            }
+        }
+
+        Format::Json => {
+            writeln!(writer, "[")?;
+
+            for (i, row) in rows.iter().enumerate() {
+                let separator = if i + 1 < rows.len() { "," } else { "" };
+                writeln!(
+                    writer,
+                    "  {{\"outcome\": {}, {}, {}, {}}}{separator}",
+                    row.outcome,
+                    json_field("probability", row.probability),
+                    json_field("at_least", &row.at_least),
+                    json_field("at_most", &row.at_most)
+                )?;
+            }
+
+            writeln!(writer, "]")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the mean, variance, standard deviation, and bounds of a total.
+pub fn write_stats<W: Write>(writer: &mut W, format: Format, stats: &Stats) -> Result<()> {
+    match format {
+        Format::Csv => {
+            writeln!(writer, "statistic,value,value_exact")?;
+            writeln!(writer, "mean,{},{}", float(&stats.mean), stats.mean)?;
+            writeln!(
+                writer,
+                "variance,{},{}",
+                float(&stats.variance),
+                stats.variance
+            )?;
+            writeln!(writer, "std_dev,{},", stats.std_dev())?;
+            writeln!(writer, "min,{},{}", float(&stats.min), stats.min)?;
+            writeln!(writer, "max,{},{}", float(&stats.max), stats.max)?;
+        }
+
+        Format::Json => {
+            writeln!(
+                writer,
+                "{{{}, {}, \"std_dev\": {}, {}, {}}}",
+                json_field("mean", &stats.mean),
+                json_field("variance", &stats.variance),
+                stats.std_dev(),
+                json_field("min", &stats.min),
+                json_field("max", &stats.max)
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The nearest floating-point number to `value`.
+fn float(value: &BigRational) -> f64 {
+    value.to_f64().unwrap_or(0.0)
+}
+
+/// `value` as a JSON string, with quotes and control characters escaped.
+pub fn json_string(value: &str) -> String {
+    let mut string = String::with_capacity(value.len() + 2);
+    string.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => string.push_str("\\\""),
+            '\\' => string.push_str("\\\\"),
+            '\n' => string.push_str("\\n"),
+            c if c.is_control() => string.push_str(&format!("\\u{:04x}", c as u32)),
+            c => string.push(c),
+        }
+    }
+
+    string.push('"');
+    string
+}
+
+/// A pair of JSON object members giving `value` as a number and as an exact
+/// fraction.
+fn json_field(name: &str, value: &BigRational) -> String {
+    format!(
+        "\"{name}\": {}, \"{name}_exact\": \"{value}\"",
+        float(value)
+    )
+}
@@ -10,7 +10,8 @@ use std::{
 };
 
 use crate::ast::{
-    Add, Div, Lit, Mul, Neg, Node, Roll, Select, Selection, Sub, Visitor, VisitorResult,
+    Add, Compare, Crit, CritRule, Div, Func, Function, Lit, Mod, Mul, Neg, Node, Pow, Roll, Select,
+    Selection, Sub, Visitor, VisitorResult,
 };
 
 use crate::eval::Error;
@@ -122,6 +123,16 @@ impl<'o, W: Write> Visitor for GraphWriter<'o, W> {
             self.write_edge(&id, &select_id, "select")?;
         }
 
+        if let Some(crit) = &node.crit {
+            let crit_id = self.write_node(&format!("{crit}"))?;
+            self.write_edge(&id, &crit_id, "crit")?;
+        }
+
+        if let Some(fumble) = &node.fumble {
+            let fumble_id = self.write_node(&format!("{fumble}"))?;
+            self.write_edge(&id, &fumble_id, "fumble")?;
+        }
+
         self.id_stack.push(id);
         Ok(())
     }
@@ -152,6 +163,21 @@ impl<'o, W: Write> Visitor for GraphWriter<'o, W> {
         Ok(())
     }
 
+    fn crit(&mut self, node: &Crit) -> VisitorResult {
+        let id = match node.rule {
+            CritRule::Double => self.write_node("Crit (Double)")?,
+            CritRule::Maximize => self.write_node("Crit (Maximize)")?,
+            CritRule::Multiply(times) => self.write_node(&format!("Crit (Multiply ×{times})"))?,
+        };
+
+        node.expr.accept(self)?;
+        let expr_id = self.id_stack.pop().ok_or(Error::StackUnderflow)?;
+        self.write_edge(&id, &expr_id, "expr")?;
+
+        self.id_stack.push(id);
+        Ok(())
+    }
+
     fn neg(&mut self, node: &Neg) -> VisitorResult {
         let id = self.write_node("-")?;
 
@@ -226,4 +252,72 @@ impl<'o, W: Write> Visitor for GraphWriter<'o, W> {
         self.id_stack.push(id);
         Ok(())
     }
+
+    fn modulo(&mut self, node: &Mod) -> VisitorResult {
+        let id = self.write_node("Modulo")?;
+
+        node.left.accept(self)?;
+        let left_id = self.id_stack.pop().ok_or(Error::StackUnderflow)?;
+
+        node.right.accept(self)?;
+        let right_id = self.id_stack.pop().ok_or(Error::StackUnderflow)?;
+
+        self.write_edge(&id, &left_id, "left")?;
+        self.write_edge(&id, &right_id, "right")?;
+
+        self.id_stack.push(id);
+        Ok(())
+    }
+
+    fn pow(&mut self, node: &Pow) -> VisitorResult {
+        let id = self.write_node("Power")?;
+
+        node.left.accept(self)?;
+        let left_id = self.id_stack.pop().ok_or(Error::StackUnderflow)?;
+
+        node.right.accept(self)?;
+        let right_id = self.id_stack.pop().ok_or(Error::StackUnderflow)?;
+
+        self.write_edge(&id, &left_id, "base")?;
+        self.write_edge(&id, &right_id, "exponent")?;
+
+        self.id_stack.push(id);
+        Ok(())
+    }
+
+    fn func(&mut self, node: &Func) -> VisitorResult {
+        let id = match node.function {
+            Function::Floor => self.write_node("Floor")?,
+            Function::Ceil => self.write_node("Ceiling")?,
+            Function::Round => self.write_node("Round")?,
+            Function::Abs => self.write_node("Absolute")?,
+            Function::Min => self.write_node("Minimum")?,
+            Function::Max => self.write_node("Maximum")?,
+        };
+
+        for (i, arg) in node.args.iter().enumerate() {
+            arg.accept(self)?;
+            let arg_id = self.id_stack.pop().ok_or(Error::StackUnderflow)?;
+            self.write_edge(&id, &arg_id, &format!("arg{}", i + 1))?;
+        }
+
+        self.id_stack.push(id);
+        Ok(())
+    }
+
+    fn compare(&mut self, node: &Compare) -> VisitorResult {
+        let id = self.write_node(&format!("Compare ({})", node.comparison))?;
+
+        node.left.accept(self)?;
+        let left_id = self.id_stack.pop().ok_or(Error::StackUnderflow)?;
+
+        node.right.accept(self)?;
+        let right_id = self.id_stack.pop().ok_or(Error::StackUnderflow)?;
+
+        self.write_edge(&id, &left_id, "left")?;
+        self.write_edge(&id, &right_id, "right")?;
+
+        self.id_stack.push(id);
+        Ok(())
+    }
 }
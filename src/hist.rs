@@ -0,0 +1,173 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module draws a probability distribution as a horizontal bar chart in
+//! the terminal.
+
+use std::{
+    collections::BTreeSet,
+    io::{Result, Write},
+};
+
+use num_traits::ToPrimitive;
+
+use crate::dist::Distribution;
+use crate::style::{bold, colored, dim, Color};
+
+/// The number of character cells used by the longest bar.
+const BAR_WIDTH: usize = 40;
+
+/// The number of character cells used by the longest bar when several
+/// distributions are drawn side by side.
+const OVERLAID_BAR_WIDTH: usize = 16;
+
+/// Colors used to tell apart distributions drawn side by side.
+const SERIES_COLORS: [Color; 6] = [
+    Color::Green,
+    Color::Cyan,
+    Color::Yellow,
+    Color::Magenta,
+    Color::Blue,
+    Color::Red,
+];
+
+/// Block characters used to draw the fractional end of a bar, in eighths.
+const PARTIAL_BLOCKS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+
+/// Writes a distribution as a bar chart with one row per outcome.
+pub struct HistogramWriter<'o, W: Write> {
+    /// The writer to which the chart is written.
+    writer: &'o mut W,
+
+    /// Whether to include the probability of rolling at least and at most
+    /// each outcome.
+    cumulative: bool,
+}
+
+impl<'o, W: Write> HistogramWriter<'o, W> {
+    pub fn new(writer: &'o mut W, cumulative: bool) -> Self {
+        Self { writer, cumulative }
+    }
+
+    pub fn write(&mut self, distribution: &Distribution) -> Result<()> {
+        let width = distribution
+            .outcomes
+            .keys()
+            .map(|outcome| outcome.to_string().len())
+            .max()
+            .unwrap_or(0);
+
+        let highest = distribution
+            .outcomes
+            .values()
+            .map(|p| p.to_f64().unwrap_or(0.0))
+            .fold(0.0, f64::max);
+
+        if self.cumulative {
+            let header = format!(
+                "{:>width$}  {:BAR_WIDTH$}  {:>7}  {:>7}  {:>7}",
+                "", "", "p", "≥", "≤"
+            );
+            writeln!(self.writer, "{}", dim(header))?;
+        }
+
+        for row in distribution.cumulative() {
+            let p = row.probability.to_f64().unwrap_or(0.0);
+            let bar = bar(if highest > 0.0 { p / highest } else { 0.0 }, BAR_WIDTH);
+
+            write!(
+                self.writer,
+                "{:>width$}  {}  {:>6.2}%",
+                bold(row.outcome),
+                colored(Color::Green, bar),
+                p * 100.0
+            )?;
+
+            if self.cumulative {
+                let cumulative = format!(
+                    "{:>6.2}%  {:>6.2}%",
+                    row.at_least.to_f64().unwrap_or(0.0) * 100.0,
+                    row.at_most.to_f64().unwrap_or(0.0) * 100.0
+                );
+                write!(self.writer, "  {}", dim(cumulative))?;
+            }
+
+            writeln!(self.writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write several distributions side by side, each in a column headed by
+    /// its label, with one row for every outcome of any of them.
+    pub fn write_overlaid(
+        &mut self,
+        labels: &[String],
+        distributions: &[Distribution],
+    ) -> Result<()> {
+        let outcomes: BTreeSet<i32> = distributions
+            .iter()
+            .flat_map(|distribution| distribution.outcomes.keys().copied())
+            .collect();
+
+        let width = outcomes
+            .iter()
+            .map(|outcome| outcome.to_string().len())
+            .max()
+            .unwrap_or(0);
+
+        // Bars share a scale so that their lengths can be compared across
+        // columns.
+        let highest = distributions
+            .iter()
+            .flat_map(|distribution| distribution.outcomes.values())
+            .map(|p| p.to_f64().unwrap_or(0.0))
+            .fold(0.0, f64::max);
+
+        write!(self.writer, "{:width$}", "")?;
+        for (i, label) in labels.iter().enumerate() {
+            let color = SERIES_COLORS[i % SERIES_COLORS.len()];
+            let heading = format!("{label:>7} {:OVERLAID_BAR_WIDTH$}", "");
+            write!(self.writer, "  {}", colored(color, bold(heading)))?;
+        }
+
+        writeln!(self.writer)?;
+
+        for outcome in outcomes {
+            write!(self.writer, "{:>width$}", bold(outcome))?;
+
+            for (i, distribution) in distributions.iter().enumerate() {
+                let color = SERIES_COLORS[i % SERIES_COLORS.len()];
+                let p = distribution
+                    .outcomes
+                    .get(&outcome)
+                    .and_then(|p| p.to_f64())
+                    .unwrap_or(0.0);
+                let bar = bar(
+                    if highest > 0.0 { p / highest } else { 0.0 },
+                    OVERLAID_BAR_WIDTH,
+                );
+
+                write!(self.writer, "  {:>6.2}% {}", p * 100.0, colored(color, bar))?;
+            }
+
+            writeln!(self.writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Draw a bar `fraction` of `width` cells long, padded with spaces to the full
+/// width.
+fn bar(fraction: f64, width: usize) -> String {
+    let eighths = (fraction.clamp(0.0, 1.0) * (width * 8) as f64).round() as usize;
+    let mut bar = "█".repeat(eighths / 8);
+
+    let partial = eighths % 8;
+    if partial > 0 {
+        bar.push(PARTIAL_BLOCKS[partial]);
+    }
+
+    format!("{bar:width$}")
+}
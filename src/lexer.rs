@@ -10,11 +10,15 @@
 //! - Contiguous sequences of decimal digits as tokenized as integers.
 //! - Contiguous sequences of alphabetic characters are tokenized as words. The
 //!   following words are recognized as valid: `d`, `k`, `kh`, `kl`, `dh`, `dl`,
-//!  `adv`, `dis`, `da`, `ad`.
+//!   `adv`, `dis`, `da`, `ad`, `cs`, `cf`, `crit`, `double`, `max`, `mult`,
+//!   `floor`, `ceil`, `round`, `abs`, `min`.
 //! - Words not listed above must not appear in the expression.
 //! - The following symbols are recognized as distinct tokens: `+`, `-`, `*`,
-//!   `/`, `%`, `(`, `)`, `[`, `]`. The symbols `×` and `÷` are also recognized
-//!   as equivalent to `*` and `/`, respectively.
+//!   `/`, `%`, `^`, `(`, `)`, `[`, `]`, `,`, `<`, `<=`, `=`, `>=`, `>`, `?`. The symbols `×`,
+//!   `÷`, `≤`, and `≥` are also recognized as equivalent to `*`, `/`, `<=`, and
+//!   `>=`, respectively.
+//! - The symbol `%` is tokenized as a die size when it immediately follows the
+//!   word `d` and as the modulo operator otherwise.
 //! - No other characters may appear in the expression.
 
 use std::{
@@ -24,8 +28,10 @@ use std::{
     str::CharIndices,
 };
 
-const VALID_WORDS: &'static [&'static str] =
-    &["d", "k", "kh", "kl", "dh", "dl", "adv", "dis", "da", "ad"];
+const VALID_WORDS: &[&str] = &[
+    "d", "k", "kh", "kl", "dh", "dl", "adv", "dis", "da", "ad", "cs", "cf", "crit", "double",
+    "max", "mult", "floor", "ceil", "round", "abs", "min",
+];
 
 /// The types of tokens that can be produced by the lexer.
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -48,14 +54,41 @@ pub enum Token<'a> {
     /// The symbol `/` or `÷`.
     Divide,
 
-    /// The symbol `%`.
+    /// The symbol `%` immediately following the word `d`.
     Percent,
 
+    /// The symbol `%` anywhere else.
+    Modulo,
+
+    /// The symbol `^`.
+    Caret,
+
     /// The symbol `(` or `[`.
     Open(char),
 
     /// The symbol `)` or `]`.
     Close(char),
+
+    /// The symbol `,`.
+    Comma,
+
+    /// The symbol `<`.
+    Less,
+
+    /// The symbol `<=` or `≤`.
+    LessEqual,
+
+    /// The symbol `=`.
+    Equal,
+
+    /// The symbol `>=` or `≥`.
+    GreaterEqual,
+
+    /// The symbol `>`.
+    Greater,
+
+    /// The symbol `?`, standing for an unknown value to be solved for.
+    Unknown,
 }
 
 /// A lexical analyzer for dice expressions. The lexer implements an `Iterator`
@@ -64,6 +97,10 @@ pub struct Lexer<'a> {
     input: &'a str,
     chars: CharIndices<'a>,
     current: Option<(usize, char)>,
+
+    /// Whether the previous token was the word `d`, in which case `%` is a die
+    /// size (as in `d%`) rather than the modulo operator.
+    after_d: bool,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -81,6 +118,7 @@ impl<'a> Lexer<'a> {
             input,
             chars,
             current: None,
+            after_d: false,
         }
     }
 
@@ -107,7 +145,7 @@ impl<'a> Iterator for Lexer<'a> {
         }
 
         // Consume whitespace
-        while self.peek().map_or(false, |c| c.is_whitespace()) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
             self.next_char();
         }
 
@@ -116,11 +154,13 @@ impl<'a> Iterator for Lexer<'a> {
             return None;
         };
 
-        if ch.is_digit(10) {
+        let after_d = std::mem::take(&mut self.after_d);
+
+        if ch.is_ascii_digit() {
             // Consume an integer (take all contiguous digits)
             let i = self.peek_position();
 
-            while self.next_char().map_or(false, |c| c.is_digit(10)) {}
+            while self.next_char().is_some_and(|c| c.is_ascii_digit()) {}
 
             let j = self.peek_position();
             let n = match self.input[i..j].parse() {
@@ -134,7 +174,7 @@ impl<'a> Iterator for Lexer<'a> {
             // Consume a word (take all contiguous alphabetic characters)
             let i = self.peek_position();
 
-            while self.next_char().map_or(false, |c| c.is_alphabetic()) {}
+            while self.next_char().is_some_and(|c| c.is_alphabetic()) {}
 
             let j = self.peek_position();
             let word = &self.input[i..j];
@@ -143,6 +183,7 @@ impl<'a> Iterator for Lexer<'a> {
                 return Some(Err(Error::InvalidWord(word.to_string())));
             }
 
+            self.after_d = word == "d";
             return Some(Ok(Token::Word(word)));
         }
 
@@ -153,11 +194,27 @@ impl<'a> Iterator for Lexer<'a> {
             '-' => Some(Ok(Token::Minus)),
             '*' | '×' => Some(Ok(Token::Times)),
             '/' | '÷' => Some(Ok(Token::Divide)),
-            '%' => Some(Ok(Token::Percent)),
+            '%' if after_d => Some(Ok(Token::Percent)),
+            '%' => Some(Ok(Token::Modulo)),
+            '^' => Some(Ok(Token::Caret)),
             '(' => Some(Ok(Token::Open('('))),
             '[' => Some(Ok(Token::Open('['))),
             ')' => Some(Ok(Token::Close(')'))),
             ']' => Some(Ok(Token::Close(']'))),
+            ',' => Some(Ok(Token::Comma)),
+            '<' | '>' if self.peek() == Some('=') => {
+                self.next_char();
+                match ch {
+                    '<' => Some(Ok(Token::LessEqual)),
+                    _ => Some(Ok(Token::GreaterEqual)),
+                }
+            }
+            '<' => Some(Ok(Token::Less)),
+            '≤' => Some(Ok(Token::LessEqual)),
+            '=' => Some(Ok(Token::Equal)),
+            '≥' => Some(Ok(Token::GreaterEqual)),
+            '>' => Some(Ok(Token::Greater)),
+            '?' => Some(Ok(Token::Unknown)),
             _ => Some(Err(Error::InvalidCharacter(ch))),
         }
     }
@@ -173,8 +230,17 @@ impl<'a> Display for Token<'a> {
             Token::Times => write!(f, "×"),
             Token::Divide => write!(f, "/"),
             Token::Percent => write!(f, "%"),
+            Token::Modulo => write!(f, "%"),
+            Token::Caret => write!(f, "^"),
             Token::Open(ch) => write!(f, "{ch}"),
             Token::Close(ch) => write!(f, "{ch}"),
+            Token::Comma => write!(f, ","),
+            Token::Less => write!(f, "<"),
+            Token::LessEqual => write!(f, "<="),
+            Token::Equal => write!(f, "="),
+            Token::GreaterEqual => write!(f, ">="),
+            Token::Greater => write!(f, ">"),
+            Token::Unknown => write!(f, "?"),
         }
     }
 }
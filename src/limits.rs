@@ -0,0 +1,36 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! Limits on the resources that parsing and evaluating an expression may use,
+//! so that hostile input cannot exhaust memory or the stack or run forever.
+
+/// Limits on the size of an expression and the work done to evaluate it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Limits {
+    /// The maximum length of an input expression in bytes. This also bounds
+    /// the length of chains of operations and selections.
+    pub max_input_length: usize,
+
+    /// The maximum depth to which parenthesized sub-expressions, negations,
+    /// exponents, and function arguments may be nested.
+    pub max_nesting_depth: usize,
+
+    /// The maximum number of dice that may be rolled during one evaluation,
+    /// including dice that are rerolled or added by a critical rule.
+    pub max_dice: usize,
+
+    /// The maximum number of times that sub-expressions may be evaluated again
+    /// by `crit(..., mult<n>)` during one evaluation.
+    pub max_repetitions: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_input_length: 1000,
+            max_nesting_depth: 64,
+            max_dice: 10_000,
+            max_repetitions: 1000,
+        }
+    }
+}
@@ -2,73 +2,106 @@
 // Licensed under the ISC license
 
 mod ast;
+mod batch;
+mod canon;
+mod cli;
+mod crit;
+mod dist;
+mod dpr;
 mod eval;
+mod export;
 mod graph;
+mod hist;
 mod lexer;
+mod limits;
 mod lookahead;
 mod parser;
 mod pp;
+mod repl;
+mod sim;
+mod solve;
+mod stats;
+mod style;
+mod template;
 
-use std::{env, fmt::Display, io::stdout, process::exit};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, stdout, BufReader},
+    num::NonZeroUsize,
+    path::Path,
+    process::exit,
+    thread,
+};
 
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use num_rational::BigRational;
+use num_traits::{One, ToPrimitive};
+use rand::Rng;
+
+use cli::{Cli, Command, OutputFormat};
+use limits::Limits;
 use parser::parse;
 use pp::PP;
+use style::{bold, colored, dim, Color};
 
 fn ok_or_exit<T, E>(result: Result<T, E>) -> T
+where
+    E: Display,
+{
+    ok_or_exit_with(result, 1)
+}
+
+/// Unwrap `result`, or print its error and exit with `status`.
+fn ok_or_exit_with<T, E>(result: Result<T, E>, status: i32) -> T
 where
     E: Display,
 {
     match result {
         Ok(value) => value,
         Err(err) => {
-            eprintln!("\x1B[31m\x1B[1mError:\x1B[22m {err}\x1B[39m");
-            exit(1);
+            style::print_error(err);
+            exit(status);
         }
     }
 }
 
-fn some_or_exit<T>(option: Option<T>, msg: &str) -> T {
-    match option {
-        Some(value) => value,
-        None => {
-            eprintln!("\x1B[31m\x1B[1mError:\x1B[22m {msg}\x1B[39m");
-            exit(0)
-        }
-    }
+/// Exit with a usage error, in the same way as for arguments rejected while
+/// parsing the command line.
+fn usage_error(kind: ErrorKind, message: &str) -> ! {
+    Cli::command().error(kind, message).exit()
 }
 
-fn eval(mut arg: Option<String>, args: &mut impl Iterator<Item = String>) {
-    let evaluation = match arg.as_deref() {
-        Some("min") => {
-            arg = args.next();
-            eval::Evaluation::Min
-        }
-        Some("mid") => {
-            arg = args.next();
-            eval::Evaluation::Mid
-        }
-        Some("max") => {
-            arg = args.next();
-            eval::Evaluation::Max
-        }
-        Some(_) => eval::Evaluation::Rand(rand::thread_rng()),
-        None => exit(0),
-    };
-
-    let mut input = String::new();
-    loop {
-        input.push_str(some_or_exit(arg, "missing expression").as_str());
-        input.push_str(" ");
-        arg = args.next();
-        if arg.is_none() {
-            break;
-        }
-    }
+fn eval(options: &cli::EvalOptions, input: String) {
+    let arithmetic = options.round.arithmetic();
+    let percentile = options.percentile;
+    let explain = options.explain;
+    let breakdown = options.breakdown;
+    let seed = options.seed();
+    let evaluation = options.evaluation(seed);
 
     // Attempt to parse the input expression.
-    let root = parse(input.as_str());
+    let root = parse(input.as_str(), &Limits::default());
     let root = ok_or_exit(root);
 
+    // With a template, only the filled-in template is printed.
+    if let Some(template) = options.template() {
+        let mut evaluator = eval::Evaluator::new(evaluation);
+        evaluator.arithmetic = arithmetic;
+        let outcome = ok_or_exit(evaluator.eval(root.as_ref()));
+        let expr = ok_or_exit(pp::to_string(root.as_ref()));
+        println!(
+            "{}",
+            template.render(&template::Values {
+                expr: &expr,
+                outcome: &outcome,
+                rolls: &evaluator.rolls,
+                seed,
+            })
+        );
+        return;
+    }
+
     // Echo the parsed expression.
     let mut stdout = stdout();
     let mut pp = PP::new(&mut stdout);
@@ -77,64 +110,588 @@ fn eval(mut arg: Option<String>, args: &mut impl Iterator<Item = String>) {
 
     // Attempt to evaluate the parsed expression.
     let mut evaluator = eval::Evaluator::new(evaluation);
+    evaluator.arithmetic = arithmetic;
+    if explain {
+        let mut number = 0;
+        evaluator.trace = Some(Box::new(move |step| {
+            number += 1;
+            println!("{} {step}", dim(format!("{number:>2}.")));
+        }));
+    }
+
     let result = evaluator.eval(root.as_ref());
 
     match result {
         Ok(result) => {
-            for roll in evaluator.rolls {
-                print!("{} ", roll);
+            if breakdown {
+                let mut pp = PP::with_dice(&mut stdout, &evaluator.rolls, &evaluator.pools);
+                ok_or_exit(root.accept(&mut pp));
+                println!(" {} {}", dim("="), bold(result.total));
+            } else {
+                for roll in &evaluator.rolls {
+                    print!("{} ", roll);
+                }
+
+                println!();
             }
 
-            println!();
-            println!("\x1B[2mtotal = \x1B[22m\x1B[1m{result}\x1B[22m");
+            if result.crits > 0 {
+                println!(
+                    "{}{}",
+                    dim("crits = "),
+                    colored(Color::Yellow, bold(result.crits))
+                );
+            }
+
+            if result.fumbles > 0 {
+                println!(
+                    "{}{}",
+                    dim("fumbles = "),
+                    colored(Color::Magenta, bold(result.fumbles))
+                );
+            }
+
+            if !breakdown {
+                println!("{}{}", dim("total = "), bold(result.total));
+            }
+
+            if percentile {
+                // Rank the total within the exact distribution of the same
+                // expression.
+                let mut evaluator = dist::DistEvaluator::new();
+                evaluator.arithmetic = arithmetic;
+                let distribution = ok_or_exit(evaluator.eval(root.as_ref()));
+                let rank = distribution
+                    .percentile(result.total)
+                    .to_f64()
+                    .unwrap_or(0.0);
+                let below = distribution.below(result.total).to_f64().unwrap_or(0.0);
+
+                println!(
+                    "{}{:.1}  {}",
+                    dim("percentile = "),
+                    bold(rank * 100.0),
+                    dim(format!("(better than {:.2}% of outcomes)", below * 100.0))
+                );
+            }
         }
         Err(err) => {
-            eprintln!("\x1B[31m\x1B[1mError:\x1B[22m {err}\x1B[39m");
+            style::print_error(err);
             exit(1);
         }
     };
 }
 
-fn graph(lang: Option<String>, args: &mut impl Iterator<Item = String>) {
-    let mut arg = args.next();
-    let mut input = String::new();
+fn batch(path: &Path, format: OutputFormat, options: &cli::EvalOptions) {
+    if format == OutputFormat::Csv {
+        usage_error(
+            ErrorKind::InvalidValue,
+            "batch results may only be written as text or json",
+        );
+    }
 
-    loop {
-        input.push_str(some_or_exit(arg, "missing expression").as_str());
-        input.push_str(" ");
-        arg = args.next();
-        if arg.is_none() {
-            break;
-        }
+    // A batch is rolled without a distribution or a trace, and always at
+    // random. With --file, clap reports these conflicts itself.
+    let unsupported = [
+        ("--percentile", options.percentile),
+        ("--explain", options.explain),
+        ("--breakdown", options.breakdown),
+        ("--min", options.min),
+        ("--max", options.max),
+    ];
+    if let Some((name, _)) = unsupported.iter().find(|(_, given)| *given) {
+        usage_error(
+            ErrorKind::ArgumentConflict,
+            &format!("the argument '-' cannot be used with '{name}'"),
+        );
     }
 
+    let template = options.template();
+    if template.is_some() && format != OutputFormat::Text {
+        usage_error(
+            ErrorKind::ArgumentConflict,
+            "--quiet and --template may only be used with text results",
+        );
+    }
+
+    let mut stdout = stdout();
+    let arithmetic = options.round.arithmetic();
+    let mut batch = batch::Batch::new(&mut stdout, format.export(), arithmetic, options.seed());
+    batch.template = template;
+    let failures = if path == Path::new("-") {
+        ok_or_exit(batch.run(io::stdin().lock()))
+    } else {
+        let file = File::open(path).map_err(|err| format!("{}: {err}", path.display()));
+        ok_or_exit(batch.run(BufReader::new(ok_or_exit(file))))
+    };
+
+    if failures > 0 {
+        exit(1);
+    }
+}
+
+fn graph(args: cli::GraphArgs) {
+    let input = args.expr.input();
+
     // Attempt to parse the input expression.
-    let root = parse(input.as_str());
+    let root = parse(input.as_str(), &Limits::default());
     let root = ok_or_exit(root);
 
     // Echo the parsed expression.
     let mut stdout = stdout();
-    let mut writer = match lang.as_deref() {
-        Some("dot") => graph::GraphWriter::new_dot(&mut stdout),
-        Some("mermaid") => graph::GraphWriter::new_mermaid(&mut stdout),
-        _ => unreachable!(),
+    let mut writer = match args.format {
+        cli::GraphFormat::Dot => graph::GraphWriter::new_dot(&mut stdout),
+        cli::GraphFormat::Mermaid => graph::GraphWriter::new_mermaid(&mut stdout),
     };
 
     ok_or_exit(writer.write(root.as_ref()));
 }
 
-fn main() {
-    // The expression to evaluate is given on the command line and may be
-    // preceded  by 'min', 'mid', or 'max' to specify the evaluation strategy.
-    // The remaining arguments (or all arguments if no strategy is given) are
-    // concatenated to form a single expression.
-    let mut args = env::args().map(|arg| arg.to_lowercase());
-    args.next();
-    let arg = args.next();
-
-    if let Some("dot" | "mermaid") = arg.as_deref() {
-        graph(arg, &mut args);
+fn dist(args: cli::DistArgs) {
+    let mut evaluator = dist::DistEvaluator::new();
+    evaluator.arithmetic = args.round.arithmetic();
+    let format = args.format.export();
+    let input = args.expr.input();
+
+    // Attempt to parse the input expression.
+    let root = parse(input.as_str(), &Limits::default());
+    let root = ok_or_exit(root);
+
+    // Attempt to compute the distribution of the parsed expression.
+    let distribution = ok_or_exit(evaluator.eval(root.as_ref()));
+    let mut stdout = stdout();
+    if let Some(format) = format {
+        ok_or_exit(export::write_distribution(
+            &mut stdout,
+            format,
+            &distribution,
+        ));
+        return;
+    }
+
+    // Echo the parsed expression.
+    let mut pp = PP::new(&mut stdout);
+    ok_or_exit(root.accept(&mut pp));
+    println!();
+
+    let width = distribution
+        .outcomes
+        .keys()
+        .map(|outcome| outcome.to_string().len())
+        .max()
+        .unwrap_or(0);
+
+    for (outcome, probability) in &distribution.outcomes {
+        let percent = probability.to_f64().unwrap_or(0.0) * 100.0;
+        println!(
+            "{outcome:>width$}  {}  {}",
+            bold(format!("{percent:>6.2}%")),
+            dim(probability)
+        );
+    }
+}
+
+fn stats(args: cli::DistArgs) {
+    let mut evaluator = stats::StatsEvaluator::new();
+    evaluator.arithmetic = args.round.arithmetic();
+    let format = args.format.export();
+    let input = args.expr.input();
+
+    // Attempt to parse the input expression.
+    let root = parse(input.as_str(), &Limits::default());
+    let root = ok_or_exit(root);
+
+    // Attempt to compute the statistics of the parsed expression.
+    let stats = ok_or_exit(evaluator.eval(root.as_ref()));
+    let mut stdout = stdout();
+    if let Some(format) = format {
+        ok_or_exit(export::write_stats(&mut stdout, format, &stats));
+        return;
+    }
+
+    // Echo the parsed expression.
+    let mut pp = PP::new(&mut stdout);
+    ok_or_exit(root.accept(&mut pp));
+    println!();
+
+    println!(
+        "{}{:.3}  {}",
+        dim("mean = "),
+        bold(stats.mean.to_f64().unwrap_or(0.0)),
+        dim(&stats.mean)
+    );
+    println!(
+        "{}{:.3}  {}",
+        dim("variance = "),
+        bold(stats.variance.to_f64().unwrap_or(0.0)),
+        dim(&stats.variance)
+    );
+    println!("{}{:.3}", dim("std dev = "), bold(stats.std_dev()));
+    println!(
+        "{}{}  {}{}",
+        dim("min = "),
+        bold(stats.min),
+        dim("max = "),
+        bold(stats.max)
+    );
+}
+
+fn compare(args: cli::CompareArgs) {
+    let mut evaluator = dist::DistEvaluator::new();
+    evaluator.arithmetic = args.round.arithmetic();
+
+    // Unlike other commands, each argument is a separate expression.
+    let inputs: Vec<String> = args.exprs.iter().map(|arg| arg.to_lowercase()).collect();
+    let labels: Vec<String> = (b'A'..)
+        .zip(&inputs)
+        .map(|(label, _)| (label as char).to_string())
+        .collect();
+
+    // Attempt to parse each expression and echo it with its label.
+    let mut stdout = stdout();
+    let mut roots = Vec::with_capacity(inputs.len());
+    for (label, input) in labels.iter().zip(&inputs) {
+        let root = ok_or_exit(parse(input.as_str(), &Limits::default()));
+        print!("{}  ", bold(label));
+        let mut pp = PP::new(&mut stdout);
+        ok_or_exit(root.accept(&mut pp));
+        println!();
+        roots.push(root);
+    }
+
+    // Attempt to compute the distribution of each expression.
+    let distributions: Vec<_> = roots
+        .iter()
+        .map(|root| ok_or_exit(evaluator.eval(root.as_ref())))
+        .collect();
+
+    println!();
+    println!("{}", dim("       mean  std dev      min      max"));
+    for (label, distribution) in labels.iter().zip(&distributions) {
+        let stats = stats::Stats::from(distribution);
+        println!(
+            "{}  {:>8.3} {:>8.3} {:>8} {:>8}",
+            bold(label),
+            stats.mean.to_f64().unwrap_or(0.0),
+            stats.std_dev(),
+            stats.min,
+            stats.max
+        );
+    }
+
+    println!();
+    for (i, (a, a_dist)) in labels.iter().zip(&distributions).enumerate() {
+        for (b, b_dist) in labels.iter().zip(&distributions).skip(i + 1) {
+            let greater = a_dist.probability_greater(b_dist);
+            let less = b_dist.probability_greater(a_dist);
+            let equal = BigRational::one() - &greater - &less;
+            let [greater, less, equal] = [greater, less, equal].map(|p| p.to_f64().unwrap_or(0.0));
+            println!(
+                "{}{}  {}{}  {}{}",
+                dim(format!("P({a} > {b}) = ")),
+                bold(format!("{:.2}%", greater * 100.0)),
+                dim(format!("P({a} < {b}) = ")),
+                bold(format!("{:.2}%", less * 100.0)),
+                dim(format!("P({a} = {b}) = ")),
+                bold(format!("{:.2}%", equal * 100.0))
+            );
+        }
+    }
+
+    println!();
+    let mut writer = hist::HistogramWriter::new(&mut stdout, false);
+    ok_or_exit(writer.write_overlaid(&labels, &distributions));
+}
+
+fn equiv(args: cli::EquivArgs) {
+    let arithmetic = args.round.arithmetic();
+
+    // As with diff and cmp, the exit status is 1 if the expressions differ and
+    // 2 if they could not be compared.
+    const TROUBLE: i32 = 2;
+
+    // Attempt to parse each expression and echo its canonical form.
+    let mut stdout = stdout();
+    let left = ok_or_exit_with(
+        parse(&args.left.to_lowercase(), &Limits::default()),
+        TROUBLE,
+    );
+    let right = ok_or_exit_with(
+        parse(&args.right.to_lowercase(), &Limits::default()),
+        TROUBLE,
+    );
+    for (label, root) in [("A", &left), ("B", &right)] {
+        let canonical = ok_or_exit_with(canon::canonicalize(root.as_ref()), TROUBLE);
+        print!("{}  ", bold(label));
+        ok_or_exit_with(canonical.accept(&mut PP::new(&mut stdout)), TROUBLE);
+        println!();
+    }
+
+    let equivalent = ok_or_exit_with(
+        canon::equivalent(
+            left.as_ref(),
+            right.as_ref(),
+            arithmetic,
+            &Limits::default(),
+        ),
+        TROUBLE,
+    );
+
+    if equivalent {
+        println!("{}", colored(Color::Green, "equivalent"));
     } else {
-        eval(arg, &mut args);
+        println!("{}", colored(Color::Red, "not equivalent"));
+        exit(1);
+    }
+}
+
+fn prob(args: cli::ExprArgs) {
+    let mut evaluator = dist::DistEvaluator::new();
+    evaluator.arithmetic = args.round.arithmetic();
+    let input = args.expr.input();
+
+    // Attempt to parse the input expression.
+    let root = parse(input.as_str(), &Limits::default());
+    let root = ok_or_exit(root);
+
+    // Echo the parsed expression.
+    let mut stdout = stdout();
+    let mut pp = PP::new(&mut stdout);
+    ok_or_exit(root.accept(&mut pp));
+    println!();
+
+    // Attempt to compute the probability that the expression is true.
+    let distribution = ok_or_exit(evaluator.eval(root.as_ref()));
+    let probability = ok_or_exit(distribution.probability());
+    let percent = probability.to_f64().unwrap_or(0.0) * 100.0;
+    println!("{}  {}", bold(format!("{percent:.2}%")), dim(probability));
+}
+
+fn solve(args: cli::SolveArgs) {
+    let arithmetic = args.round.arithmetic();
+    let input = args.expr.input();
+    let solution = ok_or_exit(solve::solve(
+        &input,
+        &Limits::default(),
+        arithmetic,
+        args.probability,
+        args.min..=args.max,
+    ));
+
+    // Echo the expression with the unknown filled in.
+    let root = ok_or_exit(parser::parse_with_unknown(
+        &input,
+        &Limits::default(),
+        solution.value,
+    ));
+
+    println!("{}{}", dim("? = "), bold(solution.value));
+    let mut stdout = stdout();
+    let mut pp = PP::new(&mut stdout);
+    ok_or_exit(root.accept(&mut pp));
+    println!();
+
+    let percent = solution.probability.to_f64().unwrap_or(0.0) * 100.0;
+    println!(
+        "{}  {}",
+        bold(format!("{percent:.2}%")),
+        dim(&solution.probability)
+    );
+}
+
+fn dpr(args: cli::DprArgs) {
+    let arithmetic = args.round.arithmetic();
+    let ac = args.ac;
+    let crit = args.crit.map(|value| ast::Threshold {
+        comparison: ast::Comparison::GreaterEqual,
+        value,
+    });
+
+    // Attempt to parse both expressions and echo them.
+    let attack = ok_or_exit(parse(&args.attack.to_lowercase(), &Limits::default()));
+    let damage = ok_or_exit(parse(&args.damage.to_lowercase(), &Limits::default()));
+
+    let mut stdout = stdout();
+    print!("{}", dim("attack = "));
+    ok_or_exit(attack.accept(&mut PP::new(&mut stdout)));
+    println!("  {}{ac}", dim("vs AC "));
+    print!("{}", dim("damage = "));
+    ok_or_exit(damage.accept(&mut PP::new(&mut stdout)));
+    println!();
+
+    // Attempt to compute the outcome of the attack.
+    let result = ok_or_exit(dpr::attack(
+        attack.as_ref(),
+        ac,
+        crit,
+        damage,
+        arithmetic,
+        &Limits::default(),
+    ));
+
+    let percent = |p: &BigRational| format!("{:.2}%", p.to_f64().unwrap_or(0.0) * 100.0);
+    println!(
+        "{}{}  {}{}  {}{}",
+        dim("hit = "),
+        bold(percent(&result.hit)),
+        dim("crit = "),
+        colored(Color::Yellow, bold(percent(&result.crit))),
+        dim("miss = "),
+        bold(percent(&result.miss))
+    );
+
+    let mean = stats::Stats::from(&result.damage).mean;
+    println!(
+        "{}{:.3}  {}",
+        dim("damage per round = "),
+        bold(mean.to_f64().unwrap_or(0.0)),
+        dim(&mean)
+    );
+
+    let mut writer = hist::HistogramWriter::new(&mut stdout, false);
+    ok_or_exit(writer.write(&result.damage));
+}
+
+fn hist(args: cli::HistArgs) {
+    let mut evaluator = dist::DistEvaluator::new();
+    evaluator.arithmetic = args.round.arithmetic();
+    let cumulative = args.cumulative;
+    let input = args.expr.input();
+
+    // Attempt to parse the input expression.
+    let root = parse(input.as_str(), &Limits::default());
+    let root = ok_or_exit(root);
+
+    // Echo the parsed expression.
+    let mut stdout = stdout();
+    let mut pp = PP::new(&mut stdout);
+    ok_or_exit(root.accept(&mut pp));
+    println!();
+
+    // Attempt to compute the distribution of the parsed expression and draw it.
+    let distribution = ok_or_exit(evaluator.eval(root.as_ref()));
+    let mut writer = hist::HistogramWriter::new(&mut stdout, cumulative);
+    ok_or_exit(writer.write(&distribution));
+}
+
+fn repl(args: cli::RoundArgs) {
+    ok_or_exit(repl::Repl::new(args.arithmetic()).run());
+}
+
+fn sim(args: cli::SimArgs) {
+    let arithmetic = args.round.arithmetic();
+    let trials = args.trials.get();
+    let seed = args.seed;
+    let threads = args.threads.map_or_else(
+        || thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        NonZeroUsize::get,
+    );
+    let input = args.expr.input();
+
+    // Attempt to parse the input expression.
+    let root = parse(input.as_str(), &Limits::default());
+    let root = ok_or_exit(root);
+
+    // Echo the parsed expression.
+    let mut stdout = stdout();
+    let mut pp = PP::new(&mut stdout);
+    ok_or_exit(root.accept(&mut pp));
+    println!();
+
+    // Pick a seed if none was given and report it so that the run can be
+    // repeated.
+    let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let simulation = ok_or_exit(sim::Simulation::run_parallel(
+        root.as_ref(),
+        arithmetic,
+        trials,
+        seed,
+        threads,
+    ));
+
+    println!("{}{trials}  {}{seed}", dim("trials = "), dim("seed = "));
+    println!(
+        "{}{:.3}  {}{:.3}",
+        dim("mean = "),
+        bold(simulation.mean()),
+        dim("std dev = "),
+        bold(simulation.std_dev())
+    );
+    println!(
+        "{}{}  {}{}",
+        dim("min = "),
+        bold(simulation.min().unwrap_or_default()),
+        dim("max = "),
+        bold(simulation.max().unwrap_or_default())
+    );
+
+    for (i, percent) in [5, 25, 50, 75, 95].into_iter().enumerate() {
+        if i > 0 {
+            print!("  ");
+        }
+
+        print!(
+            "{}{}",
+            dim(format!("p{percent} = ")),
+            bold(simulation.percentile(percent as f64).unwrap_or_default())
+        );
+    }
+
+    println!();
+
+    let mut writer = hist::HistogramWriter::new(&mut stdout, false);
+    ok_or_exit(writer.write(&simulation.distribution()));
+}
+
+fn main() {
+    let cli = Cli::parse();
+    style::init(cli.color.into());
+
+    // Only --color may be given both before and after a subcommand.
+    if cli.command.is_some() && cli.has_eval_args() {
+        usage_error(
+            ErrorKind::ArgumentConflict,
+            "options and expressions given before a subcommand are not allowed",
+        );
+    }
+
+    match cli.command {
+        Some(Command::Eval(args)) => eval(&args.options, args.expr.input()),
+        Some(Command::Graph(args)) => graph(args),
+        Some(Command::Dist(args)) => dist(args),
+        Some(Command::Stats(args)) => stats(args),
+        Some(Command::Prob(args)) => prob(args),
+        Some(Command::Compare(args)) => compare(args),
+        Some(Command::Equiv(args)) => equiv(args),
+        Some(Command::Solve(args)) => solve(args),
+        Some(Command::Dpr(args)) => dpr(args),
+        Some(Command::Hist(args)) => hist(args),
+        Some(Command::Sim(args)) => sim(args),
+        Some(Command::Repl(args)) => repl(args),
+
+        // Without a subcommand, the arguments are rolled as an expression, or
+        // read from a file, or an interactive session is started if there are
+        // none.
+        None => {
+            if let Some(path) = &cli.file {
+                batch(path, cli.format, &cli.eval);
+            } else if cli.expr == ["-"] {
+                batch(Path::new("-"), cli.format, &cli.eval);
+            } else if cli.format != OutputFormat::Text {
+                usage_error(
+                    ErrorKind::ArgumentConflict,
+                    "--format may only be used with --file",
+                );
+            } else if !cli.expr.is_empty() {
+                eval(&cli.eval, cli.expr.join(" ").to_lowercase());
+            } else if cli.eval.any() {
+                usage_error(
+                    ErrorKind::MissingRequiredArgument,
+                    "an expression to roll is required",
+                );
+            } else {
+                repl(cli.eval.round);
+            }
+        }
     }
 }
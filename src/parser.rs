@@ -6,11 +6,24 @@
 
 use std::fmt::Display;
 
-use crate::ast::{Add, Div, Lit, Mul, Neg, Node, Roll, Select, Selection, Sub};
+use crate::ast::{
+    Add, Compare, Comparison, Crit, CritRule, Div, Func, Function, Lit, Mod, Mul, Neg, Node, Pow,
+    Roll, Select, Selection, Sub, Threshold,
+};
 use crate::lexer::{Error as LexError, Lexer, Token};
+use crate::limits::Limits;
 use crate::lookahead::Lookahead;
 
-type LookaheadLexer<'a> = Lookahead<Lexer<'a>>;
+/// A lookahead over the tokens in the input that also tracks how deeply the
+/// parser has recursed into nested sub-expressions.
+struct LookaheadLexer<'a> {
+    tokens: Lookahead<Lexer<'a>>,
+    depth: usize,
+    max_depth: usize,
+
+    /// The value substituted for the unknown `?`, if it is allowed.
+    unknown: Option<i32>,
+}
 
 /// Parsing errors.
 #[derive(Debug)]
@@ -28,6 +41,15 @@ pub enum Error {
     /// parenthesis.
     MismatchedParentheses(String),
 
+    /// A function was given an argument it cannot accept.
+    InvalidArgument(String),
+
+    /// The input is longer than the configured limit.
+    TooLong(String),
+
+    /// Sub-expressions are nested more deeply than the configured limit.
+    TooDeep(String),
+
     /// An error occurred in the lexer.
     LexError(LexError),
 }
@@ -35,10 +57,32 @@ pub enum Error {
 type Result = std::result::Result<Box<dyn Node>, Error>;
 type ResultOption = std::result::Result<Option<Box<dyn Node>>, Error>;
 
-/// Parse a dice expression into an abstract syntax tree.
-pub fn parse<'a>(input: &'a str) -> Result {
-    let lexer = Lexer::new(input);
-    let mut lexer = Lookahead::new(lexer);
+/// Parse a dice expression into an abstract syntax tree, failing if the
+/// expression exceeds the length or nesting depth given by `limits`.
+pub fn parse(input: &str, limits: &Limits) -> Result {
+    parse_input(input, limits, None)
+}
+
+/// Parse a dice expression in the same way as `parse`, but replace every
+/// occurrence of the unknown `?` with `unknown`.
+pub fn parse_with_unknown(input: &str, limits: &Limits, unknown: i32) -> Result {
+    parse_input(input, limits, Some(unknown))
+}
+
+fn parse_input(input: &str, limits: &Limits, unknown: Option<i32>) -> Result {
+    if input.len() > limits.max_input_length {
+        return Err(Error::TooLong(format!(
+            "Expression is longer than {} bytes",
+            limits.max_input_length
+        )));
+    }
+
+    let mut lexer = LookaheadLexer {
+        tokens: Lookahead::new(Lexer::new(input)),
+        depth: 0,
+        max_depth: limits.max_nesting_depth,
+        unknown,
+    };
     let root = parse_root(&mut lexer)?;
 
     match lexer.peek() {
@@ -50,12 +94,78 @@ pub fn parse<'a>(input: &'a str) -> Result {
     }
 }
 
+impl<'a> LookaheadLexer<'a> {
+    fn peek(&self) -> Option<&std::result::Result<Token<'a>, LexError>> {
+        self.tokens.peek()
+    }
+
+    fn next(&mut self) -> Option<std::result::Result<Token<'a>, LexError>> {
+        self.tokens.next()
+    }
+
+    /// Enter a nested sub-expression, failing if it is nested too deeply.
+    fn enter(&mut self) -> std::result::Result<(), Error> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            return Err(Error::TooDeep(format!(
+                "Expression is nested more than {} levels deep",
+                self.max_depth
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Leave a nested sub-expression previously entered with `enter`.
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+}
+
 /// Parse the production rule:
 /// ```ebnf
-/// root = sum;
+/// root = expr;
 /// ```
 fn parse_root(lexer: &mut LookaheadLexer) -> Result {
-    parse_sum(lexer)
+    parse_expr(lexer)
+}
+
+/// Parse the production rule:
+/// ```ebnf
+/// expr = sum, [comparison, sum];
+/// ```
+fn parse_expr(lexer: &mut LookaheadLexer) -> Result {
+    let left = parse_sum(lexer)?;
+
+    let comparison = match lexer.peek() {
+        Some(Ok(token)) => as_comparison(token),
+        Some(Err(err)) => return Err(err.into()),
+        None => None,
+    };
+
+    let Some(comparison) = comparison else {
+        return Ok(left);
+    };
+
+    lexer.next();
+    let right = parse_sum(lexer)?;
+    Ok(Box::new(Compare {
+        comparison,
+        left,
+        right,
+    }))
+}
+
+/// The comparison represented by `token`, if any.
+fn as_comparison(token: &Token) -> Option<Comparison> {
+    match token {
+        Token::Less => Some(Comparison::Less),
+        Token::LessEqual => Some(Comparison::LessEqual),
+        Token::Equal => Some(Comparison::Equal),
+        Token::GreaterEqual => Some(Comparison::GreaterEqual),
+        Token::Greater => Some(Comparison::Greater),
+        _ => None,
+    }
 }
 
 /// Parse the production rule:
@@ -86,23 +196,28 @@ fn parse_sum(lexer: &mut LookaheadLexer) -> Result {
 }
 /// Parse the production rule:
 /// ```ebnf
-/// term = factor, { ("*" | "/"), factor };
+/// term = power, { ("*" | "/" | "%"), power };
 /// ```
 fn parse_term(lexer: &mut LookaheadLexer) -> Result {
-    let mut left = parse_factor(lexer)?;
+    let mut left = parse_power(lexer)?;
 
     loop {
         match lexer.peek() {
             Some(Ok(Token::Times)) => {
                 lexer.next();
-                let right = parse_factor(lexer)?;
+                let right = parse_power(lexer)?;
                 left = Box::new(Mul { left, right });
             }
             Some(Ok(Token::Divide)) => {
                 lexer.next();
-                let right = parse_factor(lexer)?;
+                let right = parse_power(lexer)?;
                 left = Box::new(Div { left, right });
             }
+            Some(Ok(Token::Modulo)) => {
+                lexer.next();
+                let right = parse_power(lexer)?;
+                left = Box::new(Mod { left, right });
+            }
             Some(Err(err)) => return Err(err.into()),
             _ => break,
         }
@@ -113,7 +228,31 @@ fn parse_term(lexer: &mut LookaheadLexer) -> Result {
 
 /// Parse the production rule:
 /// ```ebnf
-/// factor = "(", sum, ")" | negation | integer | roll;
+/// power = factor, ["^", power];
+/// ```
+fn parse_power(lexer: &mut LookaheadLexer) -> Result {
+    // Every nested sub-expression passes through here, so this is where the
+    // nesting depth is limited.
+    lexer.enter()?;
+    let left = parse_factor(lexer)?;
+
+    let power: Result = match lexer.peek() {
+        Some(Ok(Token::Caret)) => {
+            lexer.next();
+            let right = parse_power(lexer)?;
+            Ok(Box::new(Pow { left, right }))
+        }
+        Some(Err(err)) => Err(err.into()),
+        _ => Ok(left),
+    };
+
+    lexer.leave();
+    power
+}
+
+/// Parse the production rule:
+/// ```ebnf
+/// factor = "(", expr, ")" | negation | integer | roll | crit | function | "?";
 /// ```
 fn parse_factor(lexer: &mut LookaheadLexer) -> Result {
     let token = lexer.peek().cloned();
@@ -121,32 +260,9 @@ fn parse_factor(lexer: &mut LookaheadLexer) -> Result {
     match token {
         Some(Ok(Token::Open(open_ch))) => {
             lexer.next();
-            let sum = parse_sum(lexer)?;
-
-            match lexer.peek().cloned() {
-                Some(Ok(Token::Close(close_ch))) => {
-                    lexer.next();
-                    match (open_ch, close_ch) {
-                        ('(', ')') => Ok(sum),
-                        ('[', ']') => Ok(sum),
-                        _ => Err(Error::MismatchedParentheses(format!(
-                            "Closing '{close_ch}' does not match opening '{open_ch}'"
-                        ))),
-                    }
-                }
-                Some(Ok(other)) => Err(Error::UnexpectedToken(format!(
-                    "'{other}' unexpected in parenthetical",
-                ))),
-                Some(Err(err)) => return Err(err.into()),
-                None => Err(Error::UnexpectedEnd(format!(
-                    "Expression ended without closing '{}'",
-                    match open_ch {
-                        '(' => ')',
-                        '[' => ']',
-                        _ => unreachable!(),
-                    }
-                ))),
-            }
+            let expr = parse_expr(lexer)?;
+            parse_close(lexer, open_ch)?;
+            Ok(expr)
         }
 
         Some(Ok(Token::Integer(n))) => {
@@ -160,13 +276,32 @@ fn parse_factor(lexer: &mut LookaheadLexer) -> Result {
 
         Some(Ok(Token::Word("d"))) => parse_roll(lexer, 1),
 
+        Some(Ok(Token::Unknown)) => {
+            lexer.next();
+            match lexer.unknown {
+                Some(value) => Ok(Box::new(Lit { value })),
+                None => Err(Error::UnexpectedToken(
+                    "'?' may only be used when solving for an unknown".to_string(),
+                )),
+            }
+        }
+
+        Some(Ok(Token::Word("crit"))) => parse_crit(lexer),
+
+        Some(Ok(Token::Word("floor"))) => parse_function(lexer, Function::Floor),
+        Some(Ok(Token::Word("ceil"))) => parse_function(lexer, Function::Ceil),
+        Some(Ok(Token::Word("round"))) => parse_function(lexer, Function::Round),
+        Some(Ok(Token::Word("abs"))) => parse_function(lexer, Function::Abs),
+        Some(Ok(Token::Word("min"))) => parse_function(lexer, Function::Min),
+        Some(Ok(Token::Word("max"))) => parse_function(lexer, Function::Max),
+
         Some(Ok(Token::Minus)) => {
             lexer.next();
-            let right = parse_factor(lexer)?;
+            let right = parse_power(lexer)?;
             Ok(Box::new(Neg { right }))
         }
 
-        Some(Err(err)) => return Err(err.into()),
+        Some(Err(err)) => Err(err.into()),
 
         Some(Ok(other)) => Err(Error::UnexpectedToken(format!(
             "'{other}' unexpected in factor",
@@ -176,9 +311,150 @@ fn parse_factor(lexer: &mut LookaheadLexer) -> Result {
     }
 }
 
+/// Consume the closing parenthesis that matches `open_ch`.
+fn parse_close(lexer: &mut LookaheadLexer, open_ch: char) -> std::result::Result<(), Error> {
+    match lexer.peek().cloned() {
+        Some(Ok(Token::Close(close_ch))) => {
+            lexer.next();
+            match (open_ch, close_ch) {
+                ('(', ')') => Ok(()),
+                ('[', ']') => Ok(()),
+                _ => Err(Error::MismatchedParentheses(format!(
+                    "Closing '{close_ch}' does not match opening '{open_ch}'"
+                ))),
+            }
+        }
+        Some(Ok(other)) => Err(Error::UnexpectedToken(format!(
+            "'{other}' unexpected in parenthetical",
+        ))),
+        Some(Err(err)) => Err(err.into()),
+        None => Err(Error::UnexpectedEnd(format!(
+            "Expression ended without closing '{}'",
+            match open_ch {
+                '(' => ')',
+                '[' => ']',
+                _ => unreachable!(),
+            }
+        ))),
+    }
+}
+
+/// Consume the name of a function followed by an opening parenthesis, which is
+/// returned so that it can be matched by `parse_close`.
+fn parse_open(lexer: &mut LookaheadLexer, name: &str) -> std::result::Result<char, Error> {
+    match lexer.next() {
+        Some(Ok(Token::Open(open_ch))) => {
+            lexer.next();
+            Ok(open_ch)
+        }
+        Some(Ok(other)) => Err(Error::UnexpectedToken(format!(
+            "'{other}' unexpected after '{name}'"
+        ))),
+        Some(Err(err)) => Err(err.into()),
+        None => Err(Error::UnexpectedEnd(format!(
+            "Expression ended without arguments to '{name}'"
+        ))),
+    }
+}
+
+/// Parse the production rule:
+/// ```ebnf
+/// function = ("floor" | "ceil" | "round" | "abs" | "min" | "max"),
+///     "(", expr, {",", expr}, ")";
+/// ```
+///
+/// `min` and `max` accept one or more arguments while the remaining functions
+/// accept exactly one.
+fn parse_function(lexer: &mut LookaheadLexer, function: Function) -> Result {
+    let open_ch = parse_open(lexer, function.name())?;
+    let mut args = vec![parse_expr(lexer)?];
+
+    while let Some(Ok(Token::Comma)) = lexer.peek() {
+        lexer.next();
+        args.push(parse_expr(lexer)?);
+    }
+
+    parse_close(lexer, open_ch)?;
+
+    match function {
+        Function::Min | Function::Max => {}
+        _ if args.len() != 1 => {
+            return Err(Error::InvalidArgument(format!(
+                "'{}' takes exactly one argument but {} were given",
+                function.name(),
+                args.len()
+            )))
+        }
+        _ => {}
+    }
+
+    Ok(Box::new(Func { function, args }))
+}
+
+/// Parse the production rule:
+/// ```ebnf
+/// crit = "crit", "(", expr, [",", crit_rule], ")";
+/// ```
+fn parse_crit(lexer: &mut LookaheadLexer) -> Result {
+    let open_ch = parse_open(lexer, "crit")?;
+    let expr = parse_expr(lexer)?;
+
+    let rule = match lexer.peek() {
+        Some(Ok(Token::Comma)) => {
+            lexer.next();
+            parse_crit_rule(lexer)?
+        }
+        _ => CritRule::Double,
+    };
+
+    parse_close(lexer, open_ch)?;
+    let crit = Crit::new(rule, expr).map_err(|err| Error::InvalidArgument(err.to_string()))?;
+    Ok(Box::new(crit))
+}
+
 /// Parse the production rule:
 /// ```ebnf
-/// roll = [integer], "d", [integer], [selection];
+/// crit_rule = "double" | "max" | "mult", [integer];
+/// ```
+fn parse_crit_rule(lexer: &mut LookaheadLexer) -> std::result::Result<CritRule, Error> {
+    match lexer.peek() {
+        Some(Ok(Token::Word("double"))) => {
+            lexer.next();
+            Ok(CritRule::Double)
+        }
+
+        Some(Ok(Token::Word("max"))) => {
+            lexer.next();
+            Ok(CritRule::Maximize)
+        }
+
+        Some(Ok(Token::Word("mult"))) => match lexer.next() {
+            Some(Ok(Token::Integer(n))) if n >= 1 => {
+                lexer.next();
+                Ok(CritRule::Multiply(n))
+            }
+            Some(Ok(Token::Integer(n))) => Err(Error::InvalidArgument(format!(
+                "Invalid critical multiplier: {n}"
+            ))),
+            Some(Err(err)) => Err(err.into()),
+            _ => Ok(CritRule::Multiply(2)),
+        },
+
+        Some(Ok(other)) => Err(Error::UnexpectedToken(format!(
+            "'{other}' is not a critical rule"
+        ))),
+
+        Some(Err(err)) => Err(err.into()),
+
+        None => Err(Error::UnexpectedEnd(
+            "Expression ended without critical rule".to_string(),
+        )),
+    }
+}
+
+/// Parse the production rule:
+/// ```ebnf
+/// roll = [integer], "d", [integer | "%"], {critical}, [selection], {critical};
 /// ```
 fn parse_roll(lexer: &mut LookaheadLexer, count: i32) -> Result {
     let token = lexer.peek();
@@ -186,43 +462,40 @@ fn parse_roll(lexer: &mut LookaheadLexer, count: i32) -> Result {
         Some(Ok(Token::Word("d"))) => {
             let token = lexer.next();
 
-            match token {
+            let sides = match token {
                 Some(Ok(Token::Integer(sides))) => match sides {
                     4 | 6 | 8 | 10 | 12 | 20 | 100 => {
                         lexer.next();
-                        let select = parse_selection(lexer)?;
-                        Ok(Box::new(Roll {
-                            count: Box::new(Lit { value: count }),
-                            sides: Box::new(Lit { value: sides }),
-                            select,
-                        }))
+                        sides
                     }
-                    _ => Err(Error::InvalidDie(format!("Invalid die: d{sides}"))),
+                    _ => return Err(Error::InvalidDie(format!("Invalid die: d{sides}"))),
                 },
                 Some(Ok(Token::Percent)) => {
                     lexer.next();
-                    let select = parse_selection(lexer)?;
-                    Ok(Box::new(Roll {
-                        count: Box::new(Lit { value: count }),
-                        sides: Box::new(Lit { value: 100 }),
-                        select,
-                    }))
+                    100
                 }
 
                 Some(Err(err)) => return Err(err.into()),
 
-                _ => {
-                    let select = parse_selection(lexer)?;
-                    Ok(Box::new(Roll {
-                        count: Box::new(Lit { value: count }),
-                        sides: Box::new(Lit { value: 6 }),
-                        select,
-                    }))
-                }
-            }
+                _ => 6,
+            };
+
+            let mut crit = None;
+            let mut fumble = None;
+            parse_criticals(lexer, &mut crit, &mut fumble)?;
+            let select = parse_selection(lexer)?;
+            parse_criticals(lexer, &mut crit, &mut fumble)?;
+
+            Ok(Box::new(Roll {
+                count: Box::new(Lit { value: count }),
+                sides: Box::new(Lit { value: sides }),
+                select,
+                crit,
+                fumble,
+            }))
         }
 
-        Some(Err(err)) => return Err(err.into()),
+        Some(Err(err)) => Err(err.into()),
 
         Some(Ok(other)) => Err(Error::UnexpectedToken(format!(
             "'{other}' unexpected in roll",
@@ -232,6 +505,59 @@ fn parse_roll(lexer: &mut LookaheadLexer, count: i32) -> Result {
     }
 }
 
+/// Parse any number of repetitions of the production rule:
+/// ```ebnf
+/// critical = ("cs" | "cf"), [comparison], integer;
+/// comparison = "<" | "<=" | "=" | ">=" | ">";
+/// ```
+///
+/// If the comparison is omitted, only results equal to the given integer are
+/// critical. Later ranges replace earlier ones of the same kind.
+fn parse_criticals(
+    lexer: &mut LookaheadLexer,
+    crit: &mut Option<Threshold>,
+    fumble: &mut Option<Threshold>,
+) -> std::result::Result<(), Error> {
+    loop {
+        let target = match lexer.peek() {
+            Some(Ok(Token::Word("cs"))) => &mut *crit,
+            Some(Ok(Token::Word("cf"))) => &mut *fumble,
+            Some(Err(err)) => return Err(err.into()),
+            _ => return Ok(()),
+        };
+
+        let comparison = match lexer.next() {
+            Some(Ok(token)) => as_comparison(&token),
+            _ => None,
+        };
+
+        if comparison.is_some() {
+            lexer.next();
+        }
+
+        match lexer.peek() {
+            Some(Ok(Token::Integer(value))) => {
+                *target = Some(Threshold {
+                    comparison: comparison.unwrap_or(Comparison::Equal),
+                    value: *value,
+                });
+                lexer.next();
+            }
+            Some(Ok(other)) => {
+                return Err(Error::UnexpectedToken(format!(
+                    "'{other}' unexpected in critical range",
+                )))
+            }
+            Some(Err(err)) => return Err(err.into()),
+            None => {
+                return Err(Error::UnexpectedEnd(
+                    "Expression ended without critical range".to_string(),
+                ))
+            }
+        }
+    }
+}
+
 /// Parse the production rule:
 /// ```ebnf
 /// selection = (
@@ -275,7 +601,7 @@ fn parse_selection(lexer: &mut LookaheadLexer) -> ResultOption {
                     })))
                 }
 
-                Some(Err(err)) => return Err(err.into()),
+                Some(Err(err)) => Err(err.into()),
 
                 _ => Ok(Some(Box::new(Select {
                     selection,
@@ -303,7 +629,7 @@ fn parse_selection(lexer: &mut LookaheadLexer) -> ResultOption {
             })))
         }
 
-        Some(Err(err)) => return Err(err.into()),
+        Some(Err(err)) => Err(err.into()),
 
         _ => Ok(None),
     }
@@ -325,6 +651,9 @@ impl Display for Error {
             Error::UnexpectedEnd(message) => write!(f, "{message}"),
             Error::InvalidDie(message) => write!(f, "{message}"),
             Error::MismatchedParentheses(message) => write!(f, "{message}",),
+            Error::InvalidArgument(message) => write!(f, "{message}"),
+            Error::TooLong(message) => write!(f, "{message}"),
+            Error::TooDeep(message) => write!(f, "{message}"),
             Error::LexError(error) => write!(f, "{error}"),
         }
     }
@@ -341,3 +670,166 @@ impl From<&LexError> for Error {
         Error::LexError(error.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_longer_than_limit_is_rejected() {
+        let limits = Limits::default();
+        let input = "1 + ".repeat(limits.max_input_length / 4) + "1";
+        assert!(matches!(parse(&input, &limits), Err(Error::TooLong(_))));
+
+        let input = "1".repeat(limits.max_input_length + 1);
+        assert!(matches!(parse(&input, &limits), Err(Error::TooLong(_))));
+    }
+
+    #[test]
+    fn nesting_deeper_than_limit_is_rejected() {
+        let limits = Limits::default();
+        let depth = limits.max_nesting_depth + 1;
+        let parens = "(".repeat(depth) + "1" + &")".repeat(depth);
+        assert!(matches!(parse(&parens, &limits), Err(Error::TooDeep(_))));
+
+        let negations = "-".repeat(depth) + "1";
+        assert!(matches!(parse(&negations, &limits), Err(Error::TooDeep(_))));
+
+        let exponents = "2^".repeat(depth) + "1";
+        assert!(matches!(parse(&exponents, &limits), Err(Error::TooDeep(_))));
+
+        // The whole expression is the first level.
+        let depth = limits.max_nesting_depth - 1;
+        let nested = "(".repeat(depth) + "1" + &")".repeat(depth);
+        assert!(parse(&nested, &limits).is_ok());
+    }
+
+    #[test]
+    fn literals_out_of_range_are_rejected() {
+        let limits = Limits::default();
+        for input in [
+            "2147483648",
+            "99999999999",
+            "99999999999d6",
+            "1d6kh99999999999",
+        ] {
+            assert!(matches!(
+                parse(input, &limits),
+                Err(Error::LexError(LexError::ParseIntError(_)))
+            ));
+        }
+    }
+
+    #[test]
+    fn malformed_input_is_rejected_without_panicking() {
+        let limits = Limits::default();
+        for input in [
+            "",
+            " ",
+            "(",
+            ")",
+            "((1)",
+            "1)",
+            "dd",
+            "d%%",
+            "1d7",
+            "adv",
+            "crit(",
+            "crit(1d6,",
+            "max()",
+            "min(,)",
+            "abs(1, 2)",
+            "1 +",
+            "* 2",
+            "1 >= >= 2",
+            "1d20cs",
+            "1d20cs>=",
+            "1d20cs<x",
+            "€",
+            "1d6 ÷",
+            "[1)",
+            "?",
+            "2^",
+            "--",
+            "%",
+        ] {
+            assert!(parse(input, &limits).is_err(), "{input:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn unknowns_are_replaced_only_when_solving() {
+        let limits = Limits::default();
+        for (input, expected) in [
+            ("1d20 + ? >= 15", "1d20 + 3 >= 15"),
+            ("1d20 >= ?", "1d20 >= 3"),
+            ("max(1d6, ?)", "max(1d6, 3)"),
+            ("crit(1d8 + ?)", "crit(1d8 + 3)"),
+        ] {
+            let root = parse_with_unknown(input, &limits, 3).unwrap();
+            assert_eq!(crate::pp::to_string(root.as_ref()).unwrap(), expected);
+            assert!(matches!(
+                parse(input, &limits),
+                Err(Error::UnexpectedToken(_))
+            ));
+        }
+
+        let root = parse_with_unknown("1d20 + ?", &limits, -2).unwrap();
+        assert_eq!(crate::pp::to_string(root.as_ref()).unwrap(), "1d20 + -2");
+        assert!(parse_with_unknown("1d20 + ??", &limits, 3).is_err());
+    }
+
+    #[test]
+    fn powers_bind_tighter_than_negation_and_associate_right() {
+        let limits = Limits::default();
+        let printed = |input: &str| crate::pp::to_string(parse(input, &limits).unwrap().as_ref());
+        for (input, grouped) in [
+            ("2^3^2", "2^(3^2)"),
+            ("-2^2", "-(2^2)"),
+            ("2^-2^2", "2^(-(2^2))"),
+            ("2 * 3^2", "2 * (3^2)"),
+            ("-7 % 3", "(-7) % 3"),
+            ("1 + 8 % 3 * 2", "1 + ((8 % 3) * 2)"),
+        ] {
+            assert_eq!(
+                printed(input).unwrap(),
+                printed(grouped).unwrap(),
+                "{input}"
+            );
+        }
+
+        for (input, grouped) in [("2^3^2", "(2^3)^2"), ("-2^2", "(-2)^2")] {
+            assert_ne!(
+                printed(input).unwrap(),
+                printed(grouped).unwrap(),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn comparisons_bind_loosest_and_do_not_chain() {
+        let limits = Limits::default();
+        let printed = |input: &str| crate::pp::to_string(parse(input, &limits).unwrap().as_ref());
+        for (input, grouped) in [
+            ("1d20 + 5 >= 15", "(1d20 + 5) >= 15"),
+            ("2 * 3 < 1d6 - 1", "(2 * 3) < (1d6 - 1)"),
+            ("1d6 = 2^2", "1d6 = (2^2)"),
+            ("-1d4 <= -2", "(-1d4) <= (-2)"),
+            ("1d6 > 3", "1d6 > 3"),
+        ] {
+            assert_eq!(
+                printed(input).unwrap(),
+                printed(grouped).unwrap(),
+                "{input}"
+            );
+        }
+
+        // A comparison may still be used as an operand inside parentheses.
+        assert!(parse("(1d6 >= 4) + (1d6 >= 4)", &limits).is_ok());
+
+        for input in ["1 < 2 < 3", "1d6 == 4", "1d6 <> 3", "1d6 >= ", "< 3"] {
+            assert!(parse(input, &limits).is_err(), "{input:?} should not parse");
+        }
+    }
+}
@@ -4,27 +4,132 @@
 //! This module contains a pretty-printer for the dice expressions that
 //! traverses an AST and outputs a string representation of the expression.
 
-use std::io::Write;
+use std::{error::Error, io::Write, ops::Range};
 
-use crate::ast::{Add, Div, Lit, Mul, Neg, Roll, Select, Selection, Sub, Visitor, VisitorResult};
+use crate::ast::{
+    Add, Compare, Crit, CritRule, Div, Func, Lit, Mod, Mul, Neg, Node, Pow, Roll, Select,
+    Selection, Sub, Visitor, VisitorResult,
+};
+use crate::eval::DieRoll;
+use crate::style::{colored, Color};
+
+/// How tightly an operation binds its operands, from loosest to tightest.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+enum Precedence {
+    /// Comparisons, which cannot be chained.
+    Comparison,
+
+    /// Addition and subtraction.
+    Sum,
+
+    /// Multiplication, division, and modulo.
+    Product,
+
+    /// Exponents and negation.
+    Power,
+
+    /// Literals, rolls, and function calls, which never need parentheses.
+    Atom,
+}
 
 /// A pretty-printer for dice expressions.
 pub struct PP<'o, W: Write> {
     /// The writer to which the pretty-printed expression is written.
     writer: &'o mut W,
 
-    /// Whether the most recent operation node is a product (in which case,
-    /// terms will require parentheses).
-    prod: bool,
+    /// The loosest precedence that the enclosing operation accepts for its
+    /// operand without parentheses.
+    context: Precedence,
+
+    /// If given, each roll is written as the dice it rolled rather than in
+    /// dice notation.
+    dice: Option<Dice<'o>>,
+}
+
+/// The dice rolled by an evaluation of the expression being written.
+struct Dice<'o> {
+    /// Every die rolled.
+    rolls: &'o [DieRoll],
+
+    /// The pools of `rolls` made by each roll that has not been written yet,
+    /// as recorded by `Evaluator::pools`.
+    pools: Vec<(usize, Range<usize>)>,
+}
+
+/// Pretty-print `node` to a string.
+pub fn to_string(node: &dyn Node) -> Result<String, Box<dyn Error>> {
+    let mut output = Vec::new();
+    node.accept(&mut PP::new(&mut output))?;
+    Ok(String::from_utf8(output)?)
 }
 
 impl<'o, W: Write> PP<'o, W> {
     pub fn new(writer: &'o mut W) -> Self {
         Self {
             writer,
-            prod: false,
+            context: Precedence::Comparison,
+            dice: None,
+        }
+    }
+
+    /// Create a pretty-printer that writes the arithmetic of an evaluation:
+    /// each roll is replaced by its dice, with dropped dice surrounded by `~~`,
+    /// and each multiplied critical by the sum of its repetitions.
+    pub fn with_dice(
+        writer: &'o mut W,
+        rolls: &'o [DieRoll],
+        pools: &[(usize, Range<usize>)],
+    ) -> Self {
+        Self {
+            writer,
+            context: Precedence::Comparison,
+            dice: Some(Dice {
+                rolls,
+                pools: pools.to_vec(),
+            }),
         }
     }
+
+    /// Write `node` as an operand that may bind no more loosely than `context`.
+    fn operand(&mut self, context: Precedence, node: &dyn Node) -> VisitorResult {
+        let outer = self.context;
+        self.context = context;
+        let result = node.accept(self);
+        self.context = outer;
+        result
+    }
+
+    /// Write a left-associative binary operation, parenthesized if it binds more
+    /// loosely than the enclosing operation accepts.
+    fn binary(
+        &mut self,
+        prec: Precedence,
+        left: &dyn Node,
+        op: &str,
+        right: &dyn Node,
+    ) -> VisitorResult {
+        let parens = prec < self.context;
+        if parens {
+            write!(self.writer, "(")?;
+        }
+
+        // The right operand must bind more tightly so that `a - (b - c)` keeps
+        // its parentheses.
+        let right_prec = match prec {
+            Precedence::Sum => Precedence::Product,
+            _ => Precedence::Power,
+        };
+
+        self.operand(prec, left)?;
+        write!(self.writer, " {op} ")?;
+        self.operand(right_prec, right)?;
+
+        if parens {
+            write!(self.writer, ")")?;
+        }
+
+        Ok(())
+    }
 }
 
 impl<'o, W: Write> Visitor for PP<'o, W> {
@@ -34,10 +139,27 @@ impl<'o, W: Write> Visitor for PP<'o, W> {
     }
 
     fn roll(&mut self, node: &Roll) -> VisitorResult {
+        if let Some(dice) = &mut self.dice {
+            // Each time a node is rolled, its next pool is written.
+            let key = node as *const Roll as usize;
+            if let Some(i) = dice.pools.iter().position(|(node, _)| *node == key) {
+                let (_, pool) = dice.pools.remove(i);
+                return write_dice(self.writer, &dice.rolls[pool]);
+            }
+        }
+
         node.count.accept(self)?;
         write!(self.writer, "d")?;
         node.sides.accept(self)?;
 
+        if let Some(crit) = &node.crit {
+            write!(self.writer, "cs{crit}")?;
+        }
+
+        if let Some(fumble) = &node.fumble {
+            write!(self.writer, "cf{fumble}")?;
+        }
+
         if let Some(selection) = &node.select {
             selection.accept(self)?;
         }
@@ -46,92 +168,288 @@ impl<'o, W: Write> Visitor for PP<'o, W> {
     }
 
     fn select(&mut self, node: &Select) -> VisitorResult {
-        match node.selection {
-            Selection::KeepHighest => write!(self.writer, "kh")?,
-            Selection::KeepLowest => write!(self.writer, "kl")?,
-            Selection::DropHighest => write!(self.writer, "dh")?,
-            Selection::DropLowest => write!(self.writer, "dl")?,
-            Selection::Advantage => write!(self.writer, "adv")?,
-            Selection::Disadvantage => write!(self.writer, "dis")?,
-        };
+        write!(self.writer, "{}", node.selection.name())?;
+
+        let reroll =
+            node.selection == Selection::Advantage || node.selection == Selection::Disadvantage;
+
+        match &node.count {
+            Some(count) => count.accept(self)?,
+            None if !reroll => write!(self.writer, "1")?,
+            None => {}
+        }
+
+        // Separate a reroll from the next selection so that they are not read
+        // as a single word.
+        if let Some(next) = &node.next {
+            if reroll {
+                write!(self.writer, " ")?;
+            }
+
+            next.accept(self)?;
+        }
+
+        Ok(())
+    }
+
+    fn crit(&mut self, node: &Crit) -> VisitorResult {
+        // The dice already show how a critical changed each roll, so only a
+        // multiplied critical, which adds up several repetitions, is written.
+        if self.dice.is_some() {
+            let CritRule::Multiply(times) = node.rule else {
+                return node.operand().accept(self);
+            };
+
+            let parens = times > 1 && Precedence::Sum < self.context;
+            if parens {
+                write!(self.writer, "(")?;
+            }
+
+            for i in 0..times {
+                if i > 0 {
+                    write!(self.writer, " + ")?;
+                }
+
+                let context = if times > 1 {
+                    Precedence::Product
+                } else {
+                    self.context
+                };
+                self.operand(context, node.expr.as_ref())?;
+            }
 
-        if let Some(count) = &node.count {
-            count.accept(self)?;
+            if parens {
+                write!(self.writer, ")")?;
+            }
+
+            return Ok(());
+        }
+
+        write!(self.writer, "crit(")?;
+        self.operand(Precedence::Comparison, node.expr.as_ref())?;
+
+        match node.rule {
+            CritRule::Double => {}
+            CritRule::Maximize => write!(self.writer, ", max")?,
+            CritRule::Multiply(times) => write!(self.writer, ", mult{times}")?,
         }
 
+        write!(self.writer, ")")?;
         Ok(())
     }
 
     fn neg(&mut self, node: &Neg) -> VisitorResult {
-        let was_prod = self.prod;
-        self.prod = true;
+        let parens = Precedence::Power < self.context;
+        if parens {
+            write!(self.writer, "(")?;
+        }
+
         write!(self.writer, "-")?;
-        node.right.accept(self)?;
-        self.prod = was_prod;
+        self.operand(Precedence::Power, node.right.as_ref())?;
+
+        if parens {
+            write!(self.writer, ")")?;
+        }
+
         Ok(())
     }
 
     fn add(&mut self, node: &Add) -> VisitorResult {
-        let was_prod = self.prod;
-        self.prod = false;
+        self.binary(
+            Precedence::Sum,
+            node.left.as_ref(),
+            "+",
+            node.right.as_ref(),
+        )
+    }
+
+    fn sub(&mut self, node: &Sub) -> VisitorResult {
+        self.binary(
+            Precedence::Sum,
+            node.left.as_ref(),
+            "-",
+            node.right.as_ref(),
+        )
+    }
 
-        if was_prod {
+    fn mul(&mut self, node: &Mul) -> VisitorResult {
+        self.binary(
+            Precedence::Product,
+            node.left.as_ref(),
+            "×",
+            node.right.as_ref(),
+        )
+    }
+
+    fn div(&mut self, node: &Div) -> VisitorResult {
+        self.binary(
+            Precedence::Product,
+            node.left.as_ref(),
+            "/",
+            node.right.as_ref(),
+        )
+    }
+
+    fn modulo(&mut self, node: &Mod) -> VisitorResult {
+        self.binary(
+            Precedence::Product,
+            node.left.as_ref(),
+            "%",
+            node.right.as_ref(),
+        )
+    }
+
+    fn pow(&mut self, node: &Pow) -> VisitorResult {
+        let parens = Precedence::Power < self.context;
+        if parens {
             write!(self.writer, "(")?;
         }
 
-        node.left.accept(self)?;
-        write!(self.writer, " + ")?;
-        node.right.accept(self)?;
+        // Exponents are right-associative, so only the base needs parentheses
+        // around another exponent or negation.
+        self.operand(Precedence::Atom, node.left.as_ref())?;
+        write!(self.writer, "^")?;
+        self.operand(Precedence::Power, node.right.as_ref())?;
 
-        if was_prod {
+        if parens {
             write!(self.writer, ")")?;
         }
 
-        self.prod = was_prod;
         Ok(())
     }
 
-    fn sub(&mut self, node: &Sub) -> VisitorResult {
-        let was_prod = self.prod;
-        self.prod = false;
+    fn func(&mut self, node: &Func) -> VisitorResult {
+        write!(self.writer, "{}(", node.function.name())?;
+
+        for (i, arg) in node.args.iter().enumerate() {
+            if i > 0 {
+                write!(self.writer, ", ")?;
+            }
 
-        if was_prod {
+            self.operand(Precedence::Comparison, arg.as_ref())?;
+        }
+
+        write!(self.writer, ")")?;
+        Ok(())
+    }
+
+    fn compare(&mut self, node: &Compare) -> VisitorResult {
+        let parens = Precedence::Comparison < self.context;
+        if parens {
             write!(self.writer, "(")?;
         }
 
-        node.left.accept(self)?;
-        write!(self.writer, " - ")?;
-        node.right.accept(self)?;
+        // Comparisons are not associative, so neither side may be another
+        // comparison without parentheses.
+        self.operand(Precedence::Sum, node.left.as_ref())?;
+        write!(self.writer, " {} ", node.comparison)?;
+        self.operand(Precedence::Sum, node.right.as_ref())?;
 
-        if was_prod {
+        if parens {
             write!(self.writer, ")")?;
         }
 
-        self.prod = was_prod;
         Ok(())
     }
+}
 
-    fn mul(&mut self, node: &Mul) -> VisitorResult {
-        let was_prod = self.prod;
-        self.prod = true;
+/// Write the dice of a single roll, struck through if dropped and marked if
+/// they crit or fumble, and parenthesized as a sum if there is more than one.
+fn write_dice<W: Write>(writer: &mut W, dice: &[DieRoll]) -> VisitorResult {
+    if dice.is_empty() {
+        write!(writer, "0")?;
+        return Ok(());
+    }
 
-        node.left.accept(self)?;
-        write!(self.writer, " × ")?;
-        node.right.accept(self)?;
+    if dice.len() > 1 {
+        write!(writer, "(")?;
+    }
 
-        self.prod = was_prod;
-        Ok(())
+    for (i, die) in dice.iter().enumerate() {
+        if i > 0 {
+            write!(writer, " + ")?;
+        }
+
+        // Dropped dice are marked as struck through in the way that Markdown
+        // does, whether or not the output is styled.
+        let result = die.result;
+        if !die.keep {
+            write!(writer, "{}", colored(Color::Red, format!("~~{result}~~")))?;
+        } else if die.crit {
+            write!(writer, "{}", colored(Color::Yellow, format!("{result}!")))?;
+        } else if die.fumble {
+            write!(writer, "{}", colored(Color::Magenta, format!("{result}?")))?;
+        } else {
+            write!(writer, "{result}")?;
+        }
     }
 
-    fn div(&mut self, node: &Div) -> VisitorResult {
-        let was_prod = self.prod;
-        self.prod = true;
+    if dice.len() > 1 {
+        write!(writer, ")")?;
+    }
 
-        node.left.accept(self)?;
-        write!(self.writer, " / ")?;
-        node.right.accept(self)?;
+    Ok(())
+}
 
-        self.prod = was_prod;
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+    use crate::eval::{Evaluation, Evaluator};
+    use crate::limits::Limits;
+    use crate::parser::parse;
+
+    /// Evaluate `input` with every die rolling its maximum and write its
+    /// breakdown.
+    fn breakdown(input: &str) -> String {
+        let root = parse(input, &Limits::default()).unwrap();
+        let mut evaluator = Evaluator::<StepRng>::new(Evaluation::Max);
+        evaluator.eval(root.as_ref()).unwrap();
+
+        let mut output = Vec::new();
+        let mut pp = PP::with_dice(&mut output, &evaluator.rolls, &evaluator.pools);
+        root.accept(&mut pp).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn breakdowns_keep_the_grouping_of_the_expression() {
+        for (input, expected) in [
+            ("1d6 + 2", "6 + 2"),
+            ("2d6 + 1d4", "(6 + 6) + 4"),
+            ("1d6 - (1d4 - 1)", "6 - (4 - 1)"),
+            ("(1d6 - 1d4) - 1", "6 - 4 - 1"),
+            ("2 * (1d4 + 1)", "2 × (4 + 1)"),
+            ("(1d6 + 1) * -(2d4kh1 - 3)", "(6 + 1) × -((4 + ~~4~~) - 3)"),
+            ("((1d6 + 1) * 2) / (1d4 % 3)", "(6 + 1) × 2 / (4 % 3)"),
+            ("2^(1d4 + 1)", "2^(4 + 1)"),
+            ("(2d4)^2", "(4 + 4)^2"),
+            ("(1d4^2)^2", "(4^2)^2"),
+            ("max(1d4 + 1, -1d6)", "max(4 + 1, -6)"),
+            ("(1d6 >= 4) + 1", "(6 >= 4) + 1"),
+            ("1d20 + 5 >= 15", "20! + 5 >= 15"),
+        ] {
+            assert_eq!(breakdown(input), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn breakdowns_keep_negations_apart() {
+        for (input, expected) in [
+            ("-1d4", "-4"),
+            ("-2d4", "-(4 + 4)"),
+            ("-(1d6 + 2)", "-(6 + 2)"),
+            ("-(-1d4)", "--4"),
+            ("-1d4^2", "-4^2"),
+            ("(-1d4)^2", "(-4)^2"),
+            ("1d6 - -1d4", "6 - -4"),
+            ("-(1d6 * 2)", "-(6 × 2)"),
+            ("-crit(1d6, mult2)", "-(6 + 6)"),
+            ("crit(1d6 + 2, mult2) * 2", "((6 + 2) + (6 + 2)) × 2"),
+            ("-crit(2d4 - 1)", "-((4 + 4 + 4 + 4) - 1)"),
+        ] {
+            assert_eq!(breakdown(input), expected, "{input}");
+        }
     }
 }
@@ -0,0 +1,292 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module contains an interactive session for rolling dice expressions
+//! one after another.
+//!
+//! Lines are read with line editing and history, which is kept between sessions
+//! in `~/.roll_history`. Each total is numbered so that later expressions can
+//! refer to it: `$1` is the first total of the session and `$_` is the most
+//! recent. Lines beginning with `:` are meta-commands, listed by `:help`.
+
+use std::{
+    env,
+    error::Error,
+    io::{stdout, Write},
+    path::PathBuf,
+};
+
+use rand::rngs::ThreadRng;
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use crate::ast::Node;
+use crate::dist::DistEvaluator;
+use crate::eval::{Arithmetic, Evaluation, Evaluator};
+use crate::graph::GraphWriter;
+use crate::hist::HistogramWriter;
+use crate::limits::Limits;
+use crate::parser::parse;
+use crate::pp::PP;
+use crate::style::{bold, colored, dim, print_error, Color};
+
+/// The prompt shown before each line.
+const PROMPT: &str = "roll> ";
+
+/// The name of the history file in the user's home directory.
+const HISTORY_FILE: &str = ".roll_history";
+
+/// The meta-commands and a description of each, as shown by `:help`.
+const COMMANDS: [(&str, &str); 5] = [
+    (":reroll", "roll the previous expression again"),
+    (
+        ":dist [<expr>]",
+        "show the distribution of an expression or the previous one",
+    ),
+    (
+        ":dot [<expr>]",
+        "write an expression or the previous one as a DOT graph",
+    ),
+    (":help", "show this list"),
+    (":quit", "end the session (or press Ctrl-D)"),
+];
+
+/// An interactive session that remembers every total rolled.
+pub struct Repl {
+    /// How intermediate results are computed.
+    pub arithmetic: Arithmetic,
+
+    /// Limits on the work done to parse and evaluate each expression.
+    pub limits: Limits,
+
+    /// The evaluator used for every roll of the session.
+    evaluator: Evaluator<ThreadRng>,
+
+    /// Every total rolled so far, in order.
+    totals: Vec<i32>,
+
+    /// The most recently rolled expression.
+    last: Option<Box<dyn Node>>,
+}
+
+impl Repl {
+    pub fn new(arithmetic: Arithmetic) -> Self {
+        Self {
+            arithmetic,
+            limits: Limits::default(),
+            evaluator: Evaluator::new(Evaluation::Rand(rand::thread_rng())),
+            totals: Vec::new(),
+            last: None,
+        }
+    }
+
+    /// Read and execute lines until the end of input or `:quit`.
+    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut editor = DefaultEditor::new()?;
+        let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+
+        // A missing history file is expected the first time.
+        if let Some(history) = &history {
+            let _ = editor.load_history(history);
+        }
+
+        loop {
+            let line = match editor.readline(PROMPT) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(err) => return Err(Box::new(err)),
+            };
+
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            editor.add_history_entry(line)?;
+            if line == ":quit" || line == ":q" {
+                break;
+            }
+
+            if let Err(err) = self.execute(&line.to_lowercase()) {
+                print_error(err);
+            }
+        }
+
+        if let Some(history) = &history {
+            editor.save_history(history)?;
+        }
+
+        Ok(())
+    }
+
+    /// Execute a single line, which is either a meta-command or an expression
+    /// to roll.
+    fn execute(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
+        let Some(command) = line.strip_prefix(':') else {
+            let root = parse(self.substitute(line)?.as_str(), &self.limits)?;
+            return self.roll(root);
+        };
+
+        let (name, rest) = command.split_once(' ').unwrap_or((command, ""));
+        match name {
+            "reroll" | "r" => {
+                let root = self
+                    .last
+                    .take()
+                    .ok_or("no expression has been rolled yet")?;
+                self.roll(root)
+            }
+
+            "dist" => {
+                let mut evaluator = DistEvaluator::new();
+                evaluator.arithmetic = self.arithmetic;
+                evaluator.limits = self.limits;
+
+                let distribution = match self.expression(rest)? {
+                    Some(root) => evaluator.eval(root.as_ref())?,
+                    None => evaluator.eval(self.previous()?)?,
+                };
+
+                let mut stdout = stdout();
+                HistogramWriter::new(&mut stdout, false).write(&distribution)?;
+                Ok(())
+            }
+
+            "dot" => {
+                let mut stdout = stdout();
+                let mut writer = GraphWriter::new_dot(&mut stdout);
+                match self.expression(rest)? {
+                    Some(root) => writer.write(root.as_ref()),
+                    None => writer.write(self.previous()?),
+                }
+            }
+
+            "help" | "h" => {
+                println!("Enter a dice expression to roll it. Earlier totals may be used in");
+                println!("expressions as $1, $2, ... or the most recent as $_.");
+                println!();
+                for (command, description) in COMMANDS {
+                    println!("  {:<15} {description}", bold(command));
+                }
+
+                Ok(())
+            }
+
+            _ => Err(format!("unknown command ':{name}' (try ':help')").into()),
+        }
+    }
+
+    /// Parse the expression given to a meta-command, if there is one.
+    fn expression(&self, input: &str) -> Result<Option<Box<dyn Node>>, Box<dyn Error>> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(parse(self.substitute(input)?.as_str(), &self.limits)?))
+    }
+
+    /// The most recently rolled expression.
+    fn previous(&self) -> Result<&dyn Node, Box<dyn Error>> {
+        match &self.last {
+            Some(root) => Ok(root.as_ref()),
+            None => Err("no expression has been rolled yet".into()),
+        }
+    }
+
+    /// Replace each reference to an earlier total in `input` with its value.
+    fn substitute(&self, input: &str) -> Result<String, Box<dyn Error>> {
+        let mut output = String::with_capacity(input.len());
+        let mut chars = input.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                output.push(c);
+                continue;
+            }
+
+            let total = match chars.peek() {
+                Some('_') => {
+                    chars.next();
+                    *self.totals.last().ok_or("no total has been rolled yet")?
+                }
+
+                Some(c) if c.is_ascii_digit() => {
+                    let mut number = String::new();
+                    while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                        number.push(c);
+                        chars.next();
+                    }
+
+                    let index: usize = number.parse()?;
+                    *index
+                        .checked_sub(1)
+                        .and_then(|i| self.totals.get(i))
+                        .ok_or(format!("there is no total ${index}"))?
+                }
+
+                _ => return Err("expected '_' or a number after '$'".into()),
+            };
+
+            // Negative totals are parenthesized so that they bind as a whole.
+            if total < 0 {
+                output.push_str(&format!("({total})"));
+            } else {
+                output.push_str(&total.to_string());
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Roll an expression, show its dice and total, and remember both.
+    fn roll(&mut self, root: Box<dyn Node>) -> Result<(), Box<dyn Error>> {
+        self.evaluator.arithmetic = self.arithmetic;
+        self.evaluator.limits = self.limits;
+
+        // The expression can be rerolled even if this roll fails.
+        let result = self.evaluator.eval(root.as_ref());
+        self.last = Some(root);
+        let outcome = result?;
+
+        let mut stdout = stdout();
+        if let Some(root) = &self.last {
+            root.accept(&mut PP::new(&mut stdout))?;
+            writeln!(stdout)?;
+        }
+
+        for roll in &self.evaluator.rolls {
+            write!(stdout, "{} ", roll)?;
+        }
+
+        writeln!(stdout)?;
+
+        if outcome.crits > 0 {
+            writeln!(
+                stdout,
+                "{}{}",
+                dim("crits = "),
+                colored(Color::Yellow, bold(outcome.crits))
+            )?;
+        }
+
+        if outcome.fumbles > 0 {
+            writeln!(
+                stdout,
+                "{}{}",
+                dim("fumbles = "),
+                colored(Color::Magenta, bold(outcome.fumbles))
+            )?;
+        }
+
+        self.totals.push(outcome.total);
+        writeln!(
+            stdout,
+            "{}{}",
+            dim(format!("${} = ", self.totals.len())),
+            bold(outcome.total)
+        )?;
+
+        Ok(())
+    }
+}
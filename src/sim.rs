@@ -0,0 +1,203 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module estimates the distribution of a dice expression by evaluating
+//! it many times with random rolls and summarizing the totals.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+};
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::ast::Node;
+use crate::dist::Distribution;
+use crate::eval::{Arithmetic, Evaluation, Evaluator};
+
+/// The number of trials run with each generator derived from the master seed.
+/// Trials are divided into chunks of this size independently of the number of
+/// threads so that the same seed always gives the same result.
+const CHUNK_SIZE: u64 = 4096;
+
+/// The totals observed over a number of trials.
+pub struct Simulation {
+    /// The number of times each total was observed.
+    pub counts: BTreeMap<i32, u64>,
+
+    /// The total number of trials.
+    pub trials: u64,
+}
+
+impl Simulation {
+    /// Evaluate `node` `trials` times with `evaluator`, which is reused between
+    /// trials so that its buffers are only allocated once.
+    pub fn run<TRng: Rng>(
+        evaluator: &mut Evaluator<TRng>,
+        node: &dyn Node,
+        trials: u64,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut counts = BTreeMap::new();
+        for _ in 0..trials {
+            let outcome = evaluator.eval(node)?;
+            *counts.entry(outcome.total).or_insert(0) += 1;
+        }
+
+        Ok(Self { counts, trials })
+    }
+
+    /// Evaluate `node` `trials` times across `threads` threads.
+    ///
+    /// The trials are split into fixed-size chunks, each rolled with its own
+    /// generator derived from `seed` and the chunk's index. Threads take chunks
+    /// in turn and the counts are merged at the end, so the result depends
+    /// only on `seed` and not on the number of threads or how the chunks were
+    /// scheduled.
+    pub fn run_parallel(
+        node: &dyn Node,
+        arithmetic: Arithmetic,
+        trials: u64,
+        seed: u64,
+        threads: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let chunks = trials.div_ceil(CHUNK_SIZE);
+        let next_chunk = AtomicU64::new(0);
+        let threads = threads.clamp(1, chunks.max(1) as usize);
+
+        let results = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut evaluator = Evaluator::new(Evaluation::Rand(chunk_rng(seed, 0)));
+                        evaluator.arithmetic = arithmetic;
+                        let mut simulation = Self::empty();
+
+                        loop {
+                            let chunk = next_chunk.fetch_add(1, Ordering::Relaxed);
+                            if chunk >= chunks {
+                                break;
+                            }
+
+                            let start = chunk * CHUNK_SIZE;
+                            let count = CHUNK_SIZE.min(trials - start);
+                            evaluator.evaluation = Evaluation::Rand(chunk_rng(seed, chunk));
+
+                            match Self::run(&mut evaluator, node, count) {
+                                Ok(result) => simulation.merge(result),
+                                Err(err) => {
+                                    // Stop the other threads from taking more
+                                    // chunks.
+                                    next_chunk.store(chunks, Ordering::Relaxed);
+                                    return Err(err.to_string());
+                                }
+                            }
+                        }
+
+                        Ok(simulation)
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("simulation thread panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        let mut simulation = Self::empty();
+        for result in results {
+            simulation.merge(result?);
+        }
+
+        Ok(simulation)
+    }
+
+    fn empty() -> Self {
+        Self {
+            counts: BTreeMap::new(),
+            trials: 0,
+        }
+    }
+
+    /// Add the totals observed in `other` to this simulation.
+    pub fn merge(&mut self, other: Simulation) {
+        for (total, count) in other.counts {
+            *self.counts.entry(total).or_insert(0) += count;
+        }
+
+        self.trials += other.trials;
+    }
+
+    /// The smallest observed total.
+    pub fn min(&self) -> Option<i32> {
+        self.counts.keys().next().copied()
+    }
+
+    /// The largest observed total.
+    pub fn max(&self) -> Option<i32> {
+        self.counts.keys().next_back().copied()
+    }
+
+    /// The mean of the observed totals.
+    pub fn mean(&self) -> f64 {
+        let sum: i128 = self
+            .counts
+            .iter()
+            .map(|(&total, &count)| total as i128 * count as i128)
+            .sum();
+
+        sum as f64 / self.trials as f64
+    }
+
+    /// The (population) standard deviation of the observed totals.
+    pub fn std_dev(&self) -> f64 {
+        let mean = self.mean();
+        let variance: f64 = self
+            .counts
+            .iter()
+            .map(|(&total, &count)| (total as f64 - mean).powi(2) * count as f64)
+            .sum();
+
+        (variance / self.trials as f64).sqrt()
+    }
+
+    /// The smallest observed total such that at least `percent` percent of the
+    /// totals are no greater than it.
+    pub fn percentile(&self, percent: f64) -> Option<i32> {
+        let rank = (percent / 100.0 * self.trials as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (&total, &count) in &self.counts {
+            seen += count;
+            if seen >= rank {
+                return Some(total);
+            }
+        }
+
+        self.max()
+    }
+
+    /// The observed frequency of each total as a distribution.
+    pub fn distribution(&self) -> Distribution {
+        let trials = BigInt::from(self.trials);
+        let outcomes = self
+            .counts
+            .iter()
+            .map(|(&total, &count)| (total, BigRational::new(BigInt::from(count), trials.clone())))
+            .collect();
+
+        Distribution { outcomes }
+    }
+}
+
+/// The generator used for the chunk at index `chunk` of a simulation seeded
+/// with `seed`.
+fn chunk_rng(seed: u64, chunk: u64) -> StdRng {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    bytes[8..16].copy_from_slice(&chunk.to_le_bytes());
+    StdRng::from_seed(bytes)
+}
@@ -0,0 +1,162 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module searches for the value of an unknown `?` in a comparison, such
+//! as a modifier or a target number, that gives the probability closest to a
+//! desired one.
+
+use std::{error::Error, ops::RangeInclusive};
+
+use num_rational::BigRational;
+use num_traits::Signed;
+
+use crate::dist::DistEvaluator;
+use crate::eval::Arithmetic;
+use crate::limits::Limits;
+use crate::parser::{parse, parse_with_unknown};
+
+/// The value of the unknown that best achieves the desired probability.
+pub struct Solution {
+    /// The value substituted for the unknown.
+    pub value: i32,
+
+    /// The exact probability that the comparison holds with that value.
+    pub probability: BigRational,
+}
+
+/// Try every value of the unknown `?` in `input` within `range` and return the
+/// one whose probability of being true is closest to `target`. Ties go to the
+/// value closest to zero.
+///
+/// Values for which the expression cannot be evaluated (for example, because
+/// they cause a division by zero) are skipped.
+pub fn solve(
+    input: &str,
+    limits: &Limits,
+    arithmetic: Arithmetic,
+    target: f64,
+    range: RangeInclusive<i32>,
+) -> Result<Solution, Box<dyn Error>> {
+    if parse(input, limits).is_ok() {
+        return Err("Expression has no unknown '?' to solve for".into());
+    }
+
+    let mut evaluator = DistEvaluator::new();
+    evaluator.arithmetic = arithmetic;
+    evaluator.limits = *limits;
+
+    let target = BigRational::from_float(target).ok_or("probability must be a number")?;
+    let mut best: Option<(BigRational, Solution)> = None;
+    let mut first_error = None;
+
+    for value in range {
+        let root = parse_with_unknown(input, limits, value)?;
+        let probability = match evaluator
+            .eval(root.as_ref())
+            .and_then(|distribution| Ok(distribution.probability()?))
+        {
+            Ok(probability) => probability,
+            Err(err) => {
+                first_error.get_or_insert(err);
+                continue;
+            }
+        };
+
+        let error = (&probability - &target).abs();
+        let better = match &best {
+            None => true,
+            Some((best_error, best)) => {
+                error < *best_error || (error == *best_error && value.abs() < best.value.abs())
+            }
+        };
+
+        if better {
+            best = Some((error, Solution { value, probability }));
+        }
+    }
+
+    match (best, first_error) {
+        (Some((_, solution)), _) => Ok(solution),
+        (None, Some(err)) => Err(err),
+        (None, None) => Err("no values to search".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_rational::BigRational;
+
+    use super::*;
+
+    /// Solve `input` for `target` with the default limits and arithmetic.
+    fn solve_for(input: &str, target: f64) -> Result<Solution, Box<dyn Error>> {
+        solve(
+            input,
+            &Limits::default(),
+            Arithmetic::Integer,
+            target,
+            -100..=100,
+        )
+    }
+
+    fn ratio(numer: i32, denom: i32) -> BigRational {
+        BigRational::new(numer.into(), denom.into())
+    }
+
+    #[test]
+    fn targets_that_can_be_met_are_met_exactly() {
+        // 12 of the 20 faces are at least 9.
+        let solution = solve_for("1d20 + ? >= 15", 0.6).unwrap();
+        assert_eq!(solution.value, 6);
+        assert_eq!(solution.probability, ratio(3, 5));
+
+        let solution = solve_for("1d20 >= ?", 0.25).unwrap();
+        assert_eq!(solution.value, 16);
+        assert_eq!(solution.probability, ratio(1, 4));
+
+        let solution = solve_for("1d6 + ? > 0", 1.0).unwrap();
+        assert_eq!(solution.value, 0);
+        assert_eq!(solution.probability, ratio(1, 1));
+    }
+
+    #[test]
+    fn the_closest_probability_is_found_otherwise() {
+        // 2d6 is at least 7 with probability 21/36 and at least 8 with 15/36,
+        // which are equally far from a half, so the smaller value wins.
+        let solution = solve_for("2d6 >= ?", 0.5).unwrap();
+        assert_eq!(solution.value, 7);
+        assert_eq!(solution.probability, ratio(7, 12));
+
+        let solution = solve_for("1d20 + 5 >= ?", 0.42).unwrap();
+        assert_eq!(solution.value, 18);
+        assert_eq!(solution.probability, ratio(2, 5));
+    }
+
+    #[test]
+    fn unsatisfiable_targets_get_the_nearest_value() {
+        // No modifier in range makes 1d6 exceed 200, so every value is
+        // equally bad and the one closest to zero is given.
+        let solution = solve_for("1d6 + ? > 200", 0.9).unwrap();
+        assert_eq!(solution.value, 0);
+        assert_eq!(solution.probability, ratio(0, 1));
+
+        let solution = solve_for("1d6 >= ?", 1.5).unwrap();
+        assert_eq!(solution.value, 0);
+        assert_eq!(solution.probability, ratio(1, 1));
+    }
+
+    #[test]
+    fn expressions_that_cannot_be_solved_are_rejected() {
+        assert!(solve_for("1d20 >= 10", 0.5).is_err());
+        assert!(solve_for("1d20 + ?", 0.5).is_err());
+
+        let err = solve(
+            "1d6 / ? > 1",
+            &Limits::default(),
+            Arithmetic::Integer,
+            0.5,
+            0..=0,
+        );
+        assert!(err.is_err());
+    }
+}
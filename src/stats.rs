@@ -0,0 +1,348 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module contains an evaluator that traverses an AST and computes the
+//! exact mean, variance, and bounds of the expression's total.
+//!
+//! Every sub-expression rolls its own dice, so the operands of each operation
+//! are independent. This lets sums, differences, and products of plain rolls be
+//! handled analytically, without enumerating their outcomes. Any other
+//! operation falls back to computing the exact distribution of that
+//! sub-expression.
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use crate::ast::{
+    Add, Compare, Crit, Div, Func, Lit, Mod, Mul, Neg, Node, Pow, Roll, Select, Sub, Visitor,
+    VisitorResult,
+};
+use crate::dist::{DistEvaluator, Distribution, Error as DistError};
+use crate::eval::{Arithmetic, Error as EvalError};
+use crate::limits::Limits;
+
+/// Summary statistics of the total of a dice expression.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Stats {
+    /// The expected value of the total.
+    pub mean: BigRational,
+
+    /// The variance of the total.
+    pub variance: BigRational,
+
+    /// The smallest possible total.
+    pub min: BigRational,
+
+    /// The largest possible total.
+    pub max: BigRational,
+}
+
+/// An implementation of the `Visitor` trait that computes summary statistics of
+/// an expression using a stack of statistics.
+pub struct StatsEvaluator {
+    /// How intermediate results are computed. Defaults to `Arithmetic::Integer`.
+    pub arithmetic: Arithmetic,
+
+    /// Limits on the dice rolled, and on the repetitions in sub-expressions
+    /// whose distributions are computed.
+    pub limits: Limits,
+
+    /// The largest number of dice that the plain rolls evaluated so far could
+    /// roll.
+    dice: usize,
+
+    /// A stack of statistics of intermediate results. Once the traversal is
+    /// complete, the stack should contain the statistics of the expression.
+    results: Vec<Stats>,
+}
+
+impl Stats {
+    /// Statistics of a value that is always `value`.
+    fn constant(value: BigRational) -> Self {
+        Self {
+            mean: value.clone(),
+            variance: BigRational::zero(),
+            min: value.clone(),
+            max: value,
+        }
+    }
+
+    /// The standard deviation of the total.
+    pub fn std_dev(&self) -> f64 {
+        self.variance.to_f64().unwrap_or(0.0).sqrt()
+    }
+}
+
+impl From<&Distribution> for Stats {
+    fn from(distribution: &Distribution) -> Self {
+        let mut mean = BigRational::zero();
+        let mut square = BigRational::zero();
+        for (&outcome, probability) in &distribution.outcomes {
+            let outcome = BigRational::from_integer(outcome.into());
+            mean += &outcome * probability;
+            square += &outcome * &outcome * probability;
+        }
+
+        let variance = square - &mean * &mean;
+        let bound = |outcome: Option<&i32>| {
+            BigRational::from_integer(outcome.copied().unwrap_or_default().into())
+        };
+
+        Self {
+            mean,
+            variance,
+            min: bound(distribution.outcomes.keys().next()),
+            max: bound(distribution.outcomes.keys().next_back()),
+        }
+    }
+}
+
+impl StatsEvaluator {
+    pub fn new() -> Self {
+        Self {
+            arithmetic: Arithmetic::Integer,
+            limits: Limits::default(),
+            dice: 0,
+            results: Vec::new(),
+        }
+    }
+
+    pub fn eval(&mut self, node: &dyn Node) -> Result<Stats, Box<dyn std::error::Error>> {
+        self.results.clear();
+        self.dice = 0;
+
+        // Exact arithmetic rounds only the final total, which cannot be
+        // accounted for analytically, so the whole distribution is needed.
+        if let Arithmetic::Exact(_) = self.arithmetic {
+            return Ok(Stats::from(&self.distribution(node)?));
+        }
+
+        node.accept(self)?;
+        Ok(self.pop()?)
+    }
+
+    /// Pop the statistics of an intermediate result from the stack.
+    fn pop(&mut self) -> Result<Stats, EvalError> {
+        self.results.pop().ok_or(EvalError::StackUnderflow)
+    }
+
+    /// Compute the exact distribution of a sub-expression.
+    fn distribution(&self, node: &dyn Node) -> Result<Distribution, Box<dyn std::error::Error>> {
+        let mut evaluator = DistEvaluator::new();
+        evaluator.arithmetic = self.arithmetic;
+        evaluator.limits = self.limits;
+        evaluator.eval(node)
+    }
+
+    /// Push the statistics of a sub-expression that cannot be handled
+    /// analytically, computed from its exact distribution.
+    fn exact(&mut self, node: &dyn Node) -> VisitorResult {
+        let distribution = self.distribution(node)?;
+        self.results.push(Stats::from(&distribution));
+        Ok(())
+    }
+
+    /// Evaluate both operands of a binary operation.
+    fn operands(
+        &mut self,
+        left: &dyn Node,
+        right: &dyn Node,
+    ) -> Result<(Stats, Stats), Box<dyn std::error::Error>> {
+        left.accept(self)?;
+        let left = self.pop()?;
+        right.accept(self)?;
+        let right = self.pop()?;
+        Ok((left, right))
+    }
+}
+
+impl Default for StatsEvaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Visitor for StatsEvaluator {
+    fn lit(&mut self, node: &Lit) -> VisitorResult {
+        self.results.push(Stats::constant(BigRational::from_integer(
+            node.value.into(),
+        )));
+        Ok(())
+    }
+
+    fn roll(&mut self, node: &Roll) -> VisitorResult {
+        if node.select.is_some() {
+            return self.exact(node);
+        }
+
+        node.count.accept(self)?;
+        let count = self.pop()?;
+        node.sides.accept(self)?;
+        let sides = self.pop()?;
+
+        if sides.min != sides.max {
+            return Err(Box::new(DistError::VariableCount));
+        }
+
+        if count.min.is_negative() {
+            let min = count.min.to_integer().to_i32().unwrap_or(i32::MIN);
+            return Err(Box::new(EvalError::NegativeCount(min)));
+        }
+
+        let most = count.max.to_integer().to_usize().unwrap_or(usize::MAX);
+        self.dice = self.dice.saturating_add(most);
+        if self.dice > self.limits.max_dice {
+            return Err(Box::new(EvalError::TooManyDice(self.limits.max_dice)));
+        }
+
+        // A single die is uniform over 1..=sides. The law of total variance
+        // gives the variance of the total for any distribution of the count.
+        let sides = sides.max;
+        let one = BigRational::from_integer(BigInt::from(1));
+        let die_mean = (&sides + &one) / BigRational::from_integer(BigInt::from(2));
+        let die_variance = (&sides * &sides - &one) / BigRational::from_integer(BigInt::from(12));
+
+        self.results.push(Stats {
+            mean: &count.mean * &die_mean,
+            variance: &count.mean * &die_variance + &count.variance * &die_mean * &die_mean,
+            min: count.min,
+            max: count.max * sides,
+        });
+        Ok(())
+    }
+
+    fn select(&mut self, _node: &Select) -> VisitorResult {
+        // Rolls with selections are computed exactly, so a selection is only
+        // visited here if it has no roll to select from.
+        Err(Box::new(EvalError::StackUnderflow))
+    }
+
+    fn crit(&mut self, node: &Crit) -> VisitorResult {
+        self.exact(node)
+    }
+
+    fn neg(&mut self, node: &Neg) -> VisitorResult {
+        node.right.accept(self)?;
+        let right = self.pop()?;
+
+        self.results.push(Stats {
+            mean: -right.mean,
+            variance: right.variance,
+            min: -right.max,
+            max: -right.min,
+        });
+        Ok(())
+    }
+
+    fn add(&mut self, node: &Add) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+
+        self.results.push(Stats {
+            mean: left.mean + right.mean,
+            variance: left.variance + right.variance,
+            min: left.min + right.min,
+            max: left.max + right.max,
+        });
+        Ok(())
+    }
+
+    fn sub(&mut self, node: &Sub) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+
+        self.results.push(Stats {
+            mean: left.mean - right.mean,
+            variance: left.variance + right.variance,
+            min: left.min - right.max,
+            max: left.max - right.min,
+        });
+        Ok(())
+    }
+
+    fn mul(&mut self, node: &Mul) -> VisitorResult {
+        let (left, right) = self.operands(node.left.as_ref(), node.right.as_ref())?;
+
+        // For independent operands, E[XY] = E[X]E[Y] and E[(XY)²] = E[X²]E[Y²].
+        let mean = &left.mean * &right.mean;
+        let left_square = &left.variance + &left.mean * &left.mean;
+        let right_square = &right.variance + &right.mean * &right.mean;
+        let variance = left_square * right_square - &mean * &mean;
+
+        // The extremes of a product are found at the extremes of its operands.
+        let corners = [
+            &left.min * &right.min,
+            &left.min * &right.max,
+            &left.max * &right.min,
+            &left.max * &right.max,
+        ];
+
+        self.results.push(Stats {
+            mean,
+            variance,
+            min: corners
+                .iter()
+                .min()
+                .cloned()
+                .unwrap_or_else(BigRational::zero),
+            max: corners
+                .iter()
+                .max()
+                .cloned()
+                .unwrap_or_else(BigRational::zero),
+        });
+        Ok(())
+    }
+
+    fn div(&mut self, node: &Div) -> VisitorResult {
+        self.exact(node)
+    }
+
+    fn modulo(&mut self, node: &Mod) -> VisitorResult {
+        self.exact(node)
+    }
+
+    fn pow(&mut self, node: &Pow) -> VisitorResult {
+        self.exact(node)
+    }
+
+    fn func(&mut self, node: &Func) -> VisitorResult {
+        self.exact(node)
+    }
+
+    fn compare(&mut self, node: &Compare) -> VisitorResult {
+        self.exact(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn stats_match_distributions() {
+        for input in [
+            "3d6 + 2",
+            "1d6 - 2d4",
+            "2d6 * 1d4",
+            "-(1d8 * 3) + 1d4 * 1d4",
+            "4d6kh3",
+            "5d6dl1dh1",
+            "1d20adv + 5",
+            "2d6dis - 1",
+            "crit(2d6 + 3)",
+            "crit(4d6kh3)",
+            "crit(1d8, max) + 1d4",
+            "crit(1d6, mult3)",
+            "min(1d6, 1d4) + 1d6 / 2",
+            "1d20 + 5 >= 15",
+        ] {
+            let limits = Limits::default();
+            let root = parse(input, &limits).unwrap();
+            let stats = StatsEvaluator::new().eval(root.as_ref()).unwrap();
+            let distribution = DistEvaluator::new().eval(root.as_ref()).unwrap();
+
+            assert_eq!(stats, Stats::from(&distribution), "{input}");
+        }
+    }
+}
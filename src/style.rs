@@ -0,0 +1,133 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module styles text written to the terminal with ANSI escape sequences.
+//!
+//! Whether styles are written is decided once, when the program starts, for
+//! standard output and standard error separately. By default, text is only
+//! styled when written to a terminal and the `NO_COLOR` environment variable is
+//! not set. Text styled with the functions in this module is written plainly
+//! otherwise.
+
+use std::{
+    env,
+    ffi::OsStr,
+    fmt::{Display, Formatter, Result},
+    io::{stderr, stdout, IsTerminal},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Whether text written to standard output is styled.
+static STDOUT: AtomicBool = AtomicBool::new(false);
+
+/// Whether text written to standard error is styled.
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+/// When to style output.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorChoice {
+    /// Style output written to a terminal, unless `NO_COLOR` is set.
+    Auto,
+
+    /// Always style output.
+    Always,
+
+    /// Never style output.
+    Never,
+}
+
+/// Colors that text can be drawn in.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+}
+
+/// A value that is displayed between the escape sequences that start and end a
+/// style, if standard output is styled. Any width or precision it is formatted
+/// with applies to the value itself.
+pub struct Styled<T> {
+    value: T,
+    start: &'static str,
+    end: &'static str,
+}
+
+/// Decide whether each stream is styled.
+pub fn init(choice: ColorChoice) {
+    let (stdout, stderr) = match choice {
+        ColorChoice::Always => (true, true),
+        ColorChoice::Never => (false, false),
+        ColorChoice::Auto => {
+            // See https://no-color.org: any non-empty value disables color.
+            let allowed = env::var_os("NO_COLOR").unwrap_or_default().is_empty()
+                && env::var_os("TERM").as_deref() != Some(OsStr::new("dumb"));
+            (
+                allowed && stdout().is_terminal(),
+                allowed && stderr().is_terminal(),
+            )
+        }
+    };
+
+    STDOUT.store(stdout, Ordering::Relaxed);
+    STDERR.store(stderr, Ordering::Relaxed);
+}
+
+/// Whether text written to standard output is styled.
+pub fn enabled() -> bool {
+    STDOUT.load(Ordering::Relaxed)
+}
+
+/// Display `value` in bold.
+pub fn bold<T: Display>(value: T) -> Styled<T> {
+    Styled::new(value, "\x1B[1m", "\x1B[22m")
+}
+
+/// Display `value` dimmed, for labels and other secondary text.
+pub fn dim<T: Display>(value: T) -> Styled<T> {
+    Styled::new(value, "\x1B[2m", "\x1B[22m")
+}
+
+/// Display `value` in `color`.
+pub fn colored<T: Display>(color: Color, value: T) -> Styled<T> {
+    let start = match color {
+        Color::Red => "\x1B[31m",
+        Color::Green => "\x1B[32m",
+        Color::Yellow => "\x1B[33m",
+        Color::Blue => "\x1B[34m",
+        Color::Magenta => "\x1B[35m",
+        Color::Cyan => "\x1B[36m",
+    };
+
+    Styled::new(value, start, "\x1B[39m")
+}
+
+/// Print an error message to standard error.
+pub fn print_error<T: Display>(message: T) {
+    if STDERR.load(Ordering::Relaxed) {
+        eprintln!("\x1B[31m\x1B[1mError:\x1B[22m {message}\x1B[39m");
+    } else {
+        eprintln!("Error: {message}");
+    }
+}
+
+impl<T: Display> Styled<T> {
+    fn new(value: T, start: &'static str, end: &'static str) -> Self {
+        Self { value, start, end }
+    }
+}
+
+impl<T: Display> Display for Styled<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if !enabled() {
+            return self.value.fmt(f);
+        }
+
+        f.write_str(self.start)?;
+        self.value.fmt(f)?;
+        f.write_str(self.end)
+    }
+}
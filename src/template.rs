@@ -0,0 +1,296 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! This module fills in output templates such as `{expr} => {total} ({dice})`
+//! with the result of rolling an expression.
+//!
+//! A placeholder is a field name in braces; `{{` and `}}` stand for literal
+//! braces. Values are written without styling so that the output can be read
+//! by other programs.
+
+use std::{
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult, Write},
+};
+
+use crate::eval::{DieRoll, Outcome};
+
+/// A parsed output template.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+/// A run of literal text or a placeholder.
+#[derive(Debug, PartialEq, Clone)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// The values that a placeholder may stand for.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Field {
+    /// The expression, as written by the pretty-printer.
+    Expr,
+
+    /// The total of the expression.
+    Total,
+
+    /// Every die rolled, with dropped dice surrounded by `~~`.
+    Dice,
+
+    /// The dice that were kept.
+    Kept,
+
+    /// The dice that were dropped.
+    Dropped,
+
+    /// The number of kept dice that were critical successes.
+    Crits,
+
+    /// The number of kept dice that were critical failures.
+    Fumbles,
+
+    /// The seed of the random number generator.
+    Seed,
+}
+
+/// The result of a roll, as used to fill in a template.
+pub struct Values<'v> {
+    /// The normalized expression that was rolled.
+    pub expr: &'v str,
+
+    /// The outcome of the roll.
+    pub outcome: &'v Outcome,
+
+    /// Every die rolled.
+    pub rolls: &'v [DieRoll],
+
+    /// The seed of the random number generator used for the roll.
+    pub seed: u64,
+}
+
+/// Template errors.
+#[derive(Debug)]
+pub enum Error {
+    /// A placeholder named a field that does not exist.
+    UnknownField(String),
+
+    /// A `{` was not closed by a `}`.
+    Unclosed,
+
+    /// A `}` appeared outside of a placeholder without being doubled.
+    Unopened,
+}
+
+/// The placeholders that may be used, in the order they are listed in errors.
+const FIELDS: [(&str, Field); 8] = [
+    ("expr", Field::Expr),
+    ("total", Field::Total),
+    ("dice", Field::Dice),
+    ("kept", Field::Kept),
+    ("dropped", Field::Dropped),
+    ("crits", Field::Crits),
+    ("fumbles", Field::Fumbles),
+    ("seed", Field::Seed),
+];
+
+impl Template {
+    /// Parse a template.
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = input.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(Error::Unclosed),
+                        }
+                    }
+
+                    let field = FIELDS
+                        .iter()
+                        .find(|(field, _)| *field == name.trim())
+                        .map(|(_, field)| *field)
+                        .ok_or(Error::UnknownField(name))?;
+
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+
+                    parts.push(Part::Field(field));
+                }
+
+                '}' => return Err(Error::Unopened),
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// A template that is filled in with only the total.
+    pub fn total() -> Self {
+        Self {
+            parts: vec![Part::Field(Field::Total)],
+        }
+    }
+
+    /// Fill in the template with `values`.
+    pub fn render(&self, values: &Values) -> String {
+        let mut output = String::new();
+        for part in &self.parts {
+            // Writing to a string cannot fail.
+            let _ = match part {
+                Part::Text(text) => write!(output, "{text}"),
+                Part::Field(Field::Expr) => write!(output, "{}", values.expr),
+                Part::Field(Field::Total) => write!(output, "{}", values.outcome.total),
+                Part::Field(Field::Dice) => write_dice(&mut output, values.rolls.iter(), true),
+                Part::Field(Field::Kept) => {
+                    write_dice(&mut output, values.rolls.iter().filter(|r| r.keep), false)
+                }
+                Part::Field(Field::Dropped) => {
+                    write_dice(&mut output, values.rolls.iter().filter(|r| !r.keep), false)
+                }
+                Part::Field(Field::Crits) => write!(output, "{}", values.outcome.crits),
+                Part::Field(Field::Fumbles) => write!(output, "{}", values.outcome.fumbles),
+                Part::Field(Field::Seed) => write!(output, "{}", values.seed),
+            };
+        }
+
+        output
+    }
+}
+
+/// Write the results of `dice` separated by commas, with dropped dice
+/// surrounded by `~~` if `mark` is set.
+fn write_dice<'r>(
+    output: &mut String,
+    dice: impl Iterator<Item = &'r DieRoll>,
+    mark: bool,
+) -> FmtResult {
+    for (i, die) in dice.enumerate() {
+        if i > 0 {
+            write!(output, ", ")?;
+        }
+
+        if die.keep || !mark {
+            write!(output, "{}", die.result)?;
+        } else {
+            write!(output, "~~{}~~", die.result)?;
+        }
+    }
+
+    Ok(())
+}
+
+impl StdError for Error {}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Error::UnknownField(name) => {
+                let fields: Vec<&str> = FIELDS.iter().map(|(field, _)| *field).collect();
+                write!(
+                    f,
+                    "Unknown placeholder '{{{name}}}' (expected one of {})",
+                    fields.join(", ")
+                )
+            }
+            Error::Unclosed => write!(f, "Unclosed '{{' (write '{{{{' for a literal brace)"),
+            Error::Unopened => write!(f, "Unmatched '}}' (write '}}}}' for a literal brace)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn die(result: i32, keep: bool) -> DieRoll {
+        DieRoll {
+            sides: 6,
+            result,
+            keep,
+            crit: false,
+            fumble: false,
+        }
+    }
+
+    fn render(template: &str) -> String {
+        let outcome = Outcome {
+            total: 9,
+            crits: 1,
+            fumbles: 0,
+        };
+        let rolls = [die(6, true), die(1, false), die(3, true)];
+        let values = Values {
+            expr: "3d6kh2",
+            outcome: &outcome,
+            rolls: &rolls,
+            seed: 42,
+        };
+
+        Template::parse(template).unwrap().render(&values)
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(render("{{}}"), "{}");
+        assert_eq!(render("{{total}}"), "{total}");
+        assert_eq!(render("{{{total}}}"), "{9}");
+        assert_eq!(render(""), "");
+    }
+
+    #[test]
+    fn fields_are_filled_in() {
+        assert_eq!(
+            render("{expr} => {total} ({dice})"),
+            "3d6kh2 => 9 (6, ~~1~~, 3)"
+        );
+        assert_eq!(render("{kept}|{dropped}"), "6, 3|1");
+        assert_eq!(render("{crits}/{fumbles} seed={ seed }"), "1/0 seed=42");
+        assert_eq!(Template::total(), Template::parse("{total}").unwrap());
+    }
+
+    #[test]
+    fn unbalanced_braces_are_rejected() {
+        assert!(matches!(Template::parse("{total"), Err(Error::Unclosed)));
+        assert!(matches!(Template::parse("{"), Err(Error::Unclosed)));
+        assert!(matches!(Template::parse("total}"), Err(Error::Unopened)));
+        assert!(matches!(Template::parse("{total}}"), Err(Error::Unopened)));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(matches!(
+            Template::parse("{totl}"),
+            Err(Error::UnknownField(name)) if name == "totl"
+        ));
+        assert!(matches!(
+            Template::parse("{}"),
+            Err(Error::UnknownField(name)) if name.is_empty()
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2024 Jonathon Cobb
+// Licensed under the ISC license
+
+//! Tests of the command-line interface, run against the built binary.
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+/// Run the program with `args`.
+fn roll(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_roll"))
+        .args(args)
+        .output()
+        .expect("failed to run roll")
+}
+
+/// Run the program with `args`, writing `input` to its standard input.
+fn roll_with_input(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_roll"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run roll");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Run the program with `args`, check that it succeeded, and return what it
+/// printed.
+fn stdout(args: &[&str]) -> String {
+    let output = roll(args);
+    assert!(
+        output.status.success(),
+        "roll {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Check that the program prints the same thing for each of `orders`, which
+/// are the same arguments in different orders.
+fn same_output(orders: &[&[&str]]) {
+    let first = stdout(orders[0]);
+    for args in &orders[1..] {
+        assert_eq!(stdout(args), first, "roll {args:?}");
+    }
+}
+
+#[test]
+fn options_may_come_before_or_after_the_expression() {
+    same_output(&[&["--seed", "3", "4d6kh3"], &["4d6kh3", "--seed", "3"]]);
+    same_output(&[
+        &["-q", "--max", "2d6"],
+        &["2d6", "-q", "--max"],
+        &["--max", "2d6", "-q"],
+    ]);
+    same_output(&[
+        &["eval", "--breakdown", "--seed", "7", "4d6", "+", "1"],
+        &["eval", "4d6", "+", "1", "--breakdown", "--seed", "7"],
+    ]);
+    same_output(&[
+        &["dist", "--format", "csv", "2d6"],
+        &["dist", "2d6", "--format", "csv"],
+    ]);
+    same_output(&[
+        &["solve", "--p", "0.6", "1d20 + 5 >= ?"],
+        &["solve", "1d20 + 5 >= ?", "--p", "0.6"],
+    ]);
+    same_output(&[
+        &["equiv", "--round", "floor", "4d6d1", "4d6kh3"],
+        &["equiv", "4d6d1", "4d6kh3", "--round", "floor"],
+    ]);
+
+    assert_eq!(stdout(&["2d6", "-q", "--max"]), "12\n");
+}
+
+#[test]
+fn expressions_beginning_with_a_hyphen_follow_a_double_hyphen() {
+    assert_eq!(stdout(&["-q", "--max", "--", "-1d4", "+", "3"]), "-1\n");
+    assert_eq!(stdout(&["-q", "--", "-2"]), "-2\n");
+    assert_eq!(stdout(&["-q", "-2", "+", "3"]), "1\n");
+    assert!(stdout(&["stats", "--", "-1d6"]).contains("mean = -3.500"));
+
+    let output = roll(&["-1d6"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn batches_report_bad_lines_and_continue() {
+    let output = roll_with_input(&["-"], "1 + 2\n\n1 +\n# comment\n4 - 1\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1: 1 + 2 = 3\n5: 4 - 1 = 3\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Error: line 3: Unexpected end of input\n"
+    );
+
+    let output = roll_with_input(&["--file", "-"], "1 + 1\n2 + 3\n");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1: 1 + 1 = 2\n2: 2 + 3 = 5\n"
+    );
+}